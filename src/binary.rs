@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use globset::{Glob, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use anyhow::{Context, Result};
+
+/// Only the start of a file is inspected when sniffing for binary content.
+pub const SNIFF_LEN: usize = 8192;
+/// Files whose sniffed prefix has more invalid UTF-8 than this are treated as binary.
+const MAX_INVALID_UTF8_RATIO: f64 = 0.1;
+const BASE64_LINE_LEN: usize = 76;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryPolicy {
+    /// Leave the file out of the bundle.
+    Skip,
+    /// Emit an empty block recording the file's size and SHA-256.
+    #[default]
+    Placeholder,
+    /// Embed the file as base64 so the bundle stays lossless.
+    Base64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BinaryRule {
+    pub pattern: String,
+    pub policy: BinaryPolicy,
+}
+
+/// Resolves the binary policy for a file: the first matching rule wins,
+/// otherwise the default policy applies.
+pub struct BinaryRules {
+    default: BinaryPolicy,
+    rules: Vec<(GlobMatcher, BinaryPolicy)>,
+}
+
+impl BinaryRules {
+    pub fn new(default: BinaryPolicy, rules: &[BinaryRule]) -> Result<Self> {
+        let mut compiled = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let glob = Glob::new(&rule.pattern)
+                .context(format!("Invalid glob pattern: {}", rule.pattern))?;
+            compiled.push((glob.compile_matcher(), rule.policy));
+        }
+        Ok(BinaryRules { default, rules: compiled })
+    }
+
+    pub fn policy_for(&self, rel_path: &Path) -> BinaryPolicy {
+        self.rules
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(rel_path))
+            .map(|(_, policy)| *policy)
+            .unwrap_or(self.default)
+    }
+}
+
+pub fn is_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..bytes.len().min(SNIFF_LEN)];
+    if sample.is_empty() {
+        return false;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+
+    let mut invalid = 0;
+    for chunk in sample.utf8_chunks() {
+        invalid += chunk.invalid().len();
+    }
+    // A multi-byte character cut off by the sniff window is not evidence of binary data.
+    if sample.len() < bytes.len() {
+        let tail = sample.utf8_chunks().last().map_or(0, |c| c.invalid().len());
+        if tail <= 3 {
+            invalid -= tail;
+        }
+    }
+    invalid as f64 / sample.len() as f64 > MAX_INVALID_UTF8_RATIO
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// [`sha256_hex`] of the file at `path`, without reading it into memory.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).context("Failed to open file")?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).context("Failed to read file")?;
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Encodes `bytes` as base64 wrapped at 76 columns, one line per row.
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let encoded = BASE64.encode(bytes);
+    let mut wrapped = String::with_capacity(encoded.len() + encoded.len() / BASE64_LINE_LEN + 1);
+    for line in encoded.as_bytes().chunks(BASE64_LINE_LEN) {
+        // base64 output is pure ASCII, so every chunk is valid UTF-8.
+        wrapped.push_str(std::str::from_utf8(line).unwrap_or_default());
+        wrapped.push('\n');
+    }
+    wrapped
+}
+
+pub fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let joined: String = text.split_whitespace().collect();
+    BASE64.decode(joined).context("Invalid base64 content")
+}
@@ -0,0 +1,953 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::SystemTime;
+use ignore::WalkBuilder;
+use serde::Serialize;
+use anyhow::{Context, Result};
+use crate::binary::{self, BinaryPolicy, BinaryRules};
+use crate::comments::{self, StripComments};
+use crate::config::{Config, RootConfig};
+use crate::eol::{self, LineEndings, TextLayout};
+use crate::filter::{should_skip, Filters};
+use crate::git::{self, Blob, BlobReader, ChangeSet};
+use crate::limits::{ByteBudget, Limited, SizeLimits, Truncation};
+use crate::order::sort_entries;
+use crate::parallel;
+use crate::language::{self, LanguageFilter, LanguageRule};
+use crate::output::{BundleWriter, FileContent, FileMeta, FileRecord, HeaderField, OutputFormat, WriterOptions};
+use crate::preamble::{self, Preamble};
+use crate::references::References;
+use crate::spool::{Section, Spool};
+use crate::tokens::{TokenBudget, Tokenizer};
+use crate::tree;
+
+/// A file selected for the bundle.
+#[derive(Clone, Debug)]
+pub struct BundleEntry {
+    /// Location on disk; for a file read from a git commit, where it is in the working tree.
+    pub path: PathBuf,
+    /// Path as written into the bundle: relative to its root, prefixed with the root's label.
+    pub rel_path: PathBuf,
+    /// Path relative to its root, without the label, as matched by the config rules.
+    pub root_rel_path: PathBuf,
+    /// Deleted in the selected git changes; bundled as a tombstone without content.
+    pub deleted: bool,
+    /// Set when the file is read from a git commit instead of from disk.
+    pub blob: Option<Blob>,
+    /// Unchanged, but referenced by a changed file; see [`Bundler::references`].
+    pub referenced: bool,
+}
+
+type EntryFilter = Box<dyn Fn(&BundleEntry) -> bool + Send + Sync>;
+
+type WriterFactory = Box<dyn for<'a> Fn(Box<dyn Write + 'a>) -> Box<dyn BundleWriter + 'a> + Send + Sync>;
+
+/// A processed file with its token and line counts.
+type CountedRecord = (FileRecord, usize, usize);
+
+/// Builds a bundle from one or more root directories.
+///
+/// When no roots are added, the roots listed in the config are used.
+///
+/// ```no_run
+/// use file_bundler::{Bundler, Config};
+///
+/// let summary = Bundler::new()
+///     .root("src")
+///     .config(Config::default())
+///     .filter(|entry| !entry.rel_path.ends_with("generated.rs"))
+///     .bundle(std::io::stdout())?;
+/// println!("{} files", summary.files.len());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Default)]
+pub struct Bundler {
+    roots: Vec<RootConfig>,
+    config: Config,
+    format: Option<OutputFormat>,
+    writer: Option<WriterFactory>,
+    filters: Vec<EntryFilter>,
+    changes: Option<ChangeSet>,
+    diff: bool,
+    references: bool,
+    revision: Option<String>,
+}
+
+/// Size of one bundled file as written into the bundle.
+#[derive(Serialize)]
+pub struct FileStats {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<&'static str>,
+    pub bytes: u64,
+    pub lines: usize,
+    pub tokens: usize,
+}
+
+/// Totals over the bundled files of one language.
+pub struct LanguageStats {
+    /// `None` for files whose language was not detected.
+    pub language: Option<&'static str>,
+    pub files: usize,
+    pub bytes: u64,
+    pub lines: usize,
+    pub tokens: usize,
+}
+
+/// What ended up in a bundle and what was left out.
+#[derive(Default)]
+pub struct BundleSummary {
+    pub files: Vec<FileStats>,
+    pub total_tokens: usize,
+    pub total_bytes: u64,
+    /// Files left out because `max_total_bytes` was reached.
+    pub left_out_bytes: Vec<(String, u64)>,
+    /// Files left out because `max_tokens` was reached.
+    pub left_out_tokens: Vec<(String, usize)>,
+    /// Files left out for exceeding a per-file limit under the `skip` truncation strategy,
+    /// with their size.
+    pub left_out_size: Vec<(String, u64)>,
+    /// Files that could not be bundled, with the reason.
+    pub warnings: Vec<(String, String)>,
+}
+
+impl BundleSummary {
+    /// Per-language totals, most tokens first.
+    pub fn by_language(&self) -> Vec<LanguageStats> {
+        let mut totals: Vec<LanguageStats> = Vec::new();
+        for file in &self.files {
+            let stats = match totals.iter().position(|s| s.language == file.language) {
+                Some(idx) => &mut totals[idx],
+                None => {
+                    totals.push(LanguageStats { language: file.language, files: 0, bytes: 0, lines: 0, tokens: 0 });
+                    totals.last_mut().expect("just pushed")
+                }
+            };
+            stats.files += 1;
+            stats.bytes += file.bytes;
+            stats.lines += file.lines;
+            stats.tokens += file.tokens;
+        }
+        totals.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.language.cmp(&b.language)));
+        totals
+    }
+}
+
+impl Bundler {
+    pub fn new() -> Self {
+        Bundler::default()
+    }
+
+    /// Adds a directory to walk.
+    pub fn root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.roots.push(RootConfig::new(path));
+        self
+    }
+
+    /// Adds a directory to walk with its own label and rules.
+    pub fn add_root(mut self, root: RootConfig) -> Self {
+        self.roots.push(root);
+        self
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config_ref(&self) -> &Config {
+        &self.config
+    }
+
+    /// Overrides the output format from the config.
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Renders the bundle with a custom [`BundleWriter`] instead of one of the built-in
+    /// formats. `writer` is called once per bundle with the sink to write to.
+    pub fn writer(
+        mut self,
+        writer: impl for<'a> Fn(Box<dyn Write + 'a>) -> Box<dyn BundleWriter + 'a> + Send + Sync + 'static,
+    ) -> Self {
+        self.writer = Some(Box::new(writer));
+        self
+    }
+
+    /// Bundles only the files changed according to `changes` in the git repository of each
+    /// root, instead of every file. Their content is read from the newer revision of a
+    /// range, or else from the working tree; deleted files are listed as tombstones.
+    pub fn changes(mut self, changes: ChangeSet) -> Self {
+        self.changes = Some(changes);
+        self
+    }
+
+    /// Also writes the unified diff of the [`changes`](Bundler::changes), before the files.
+    pub fn diff(mut self, enabled: bool) -> Self {
+        self.diff = enabled;
+        self
+    }
+
+    /// Also bundles the unchanged files that the changed files refer to, as found by
+    /// [`References`], after the changed ones.
+    pub fn references(mut self, enabled: bool) -> Self {
+        self.references = enabled;
+        self
+    }
+
+    /// Bundles the files of commit `revision` (a hash, tag or branch) in the git repository
+    /// of each root, read from the object database without checking it out. Such files
+    /// are always read into memory, however large.
+    pub fn revision(mut self, revision: impl Into<String>) -> Self {
+        self.revision = Some(revision.into());
+        self
+    }
+
+    /// Adds a predicate applied after the config rules; entries for which it returns false are left out.
+    pub fn filter(mut self, filter: impl Fn(&BundleEntry) -> bool + Send + Sync + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Walks the roots and yields every file that passes the config rules and custom filters,
+    /// in the configured order.
+    pub fn entries(&self) -> Result<impl Iterator<Item = BundleEntry> + '_> {
+        let entries = self.walk(None)?.into_iter().filter_map(|(entry, included)| included.then_some(entry));
+        self.sorted(entries.collect())
+    }
+
+    fn sorted(&self, mut entries: Vec<BundleEntry>) -> Result<impl Iterator<Item = BundleEntry> + '_> {
+        sort_entries(&mut entries, self.config.order, &self.config.priority)?;
+        // Referenced files follow the changed ones; the sort is stable.
+        entries.sort_by_key(|entry| entry.referenced);
+        Ok(entries.into_iter())
+    }
+
+    /// Walks the roots and returns every file not ignored by git, or every changed file
+    /// when [`changes`](Bundler::changes) is set, with whether it passes the config rules
+    /// and custom filters. The `output` file and its temporary files are left out.
+    fn walk(&self, output: Option<&OutputFile>) -> Result<Vec<(BundleEntry, bool)>> {
+        let roots = self.effective_roots();
+        if roots.is_empty() {
+            return Err(anyhow::anyhow!("No input directories given"));
+        }
+        if self.changes.is_some() && self.revision.is_some() {
+            return Err(anyhow::anyhow!("Changed files and a revision cannot be bundled together"));
+        }
+        if (self.diff || self.references) && self.changes.is_none() {
+            return Err(anyhow::anyhow!("A diff or referenced files need changed files to start from"));
+        }
+        // Roots sharing a label would yield the same paths in the bundle.
+        let mut labels: HashMap<PathBuf, &Path> = HashMap::new();
+        for root in roots {
+            let label = root_label(root, roots.len()).unwrap_or_default();
+            if let Some(other) = labels.insert(label.clone(), &root.path) {
+                return Err(anyhow::anyhow!(
+                    "Input directories {} and {} have the same label {}; label them apart as label=path",
+                    other.display(),
+                    root.path.display(),
+                    label.display()
+                ));
+            }
+        }
+        let languages = LanguageFilter::new(&self.config.include_languages, &self.config.exclude_languages)?;
+        let mut entries = Vec::new();
+        for root in roots {
+            if !root.path.is_dir() {
+                return Err(anyhow::anyhow!("Input path must be an existing directory: {}", root.path.display()));
+            }
+            let label = root_label(root, roots.len());
+            let filters = Filters::for_root(&self.config, root)?;
+            let blobs = match self.reads_blobs() && (languages.is_active() || self.references) {
+                true => Some(BlobReader::new(&root.path)?),
+                false => None,
+            };
+            let entry = |path: PathBuf, deleted, blob, referenced| {
+                let root_rel_path = path.strip_prefix(&root.path).unwrap_or(&path).to_path_buf();
+                let skipped = should_skip(&root_rel_path, false, &filters);
+                let rel_path = match &label {
+                    Some(label) => label.join(&root_rel_path),
+                    None => root_rel_path.clone(),
+                };
+                let entry = BundleEntry { rel_path, root_rel_path, path, deleted, blob, referenced };
+                let included = !skipped
+                    && self.filters.iter().all(|f| f(&entry))
+                    && (!languages.is_active() || languages.allows(sniff_language(&entry, blobs.as_ref())));
+                (entry, included)
+            };
+
+            // An output file inside a root, or its temporary file, must not end up in the bundle.
+            let is_output =
+                |path: &Path, blob: &Option<Blob>| blob.is_none() && output.is_some_and(|o| o.matches(path));
+            let first = entries.len();
+            for (path, deleted, blob) in self.list_files(&root.path)? {
+                if !is_output(&path, &blob) {
+                    entries.push(entry(path, deleted, blob, false));
+                }
+            }
+            if self.references {
+                let mut references = References::default();
+                for (changed, _) in entries[first..].iter().filter(|(e, included)| *included && !e.deleted) {
+                    if let Some(text) = read_text(changed, blobs.as_ref()) {
+                        references.add(&text);
+                    }
+                }
+                let changed: HashSet<PathBuf> = entries[first..].iter().map(|(e, _)| e.path.clone()).collect();
+                for (path, _, blob) in self.all_files(&root.path)? {
+                    let rel_path = path.strip_prefix(&root.path).unwrap_or(&path);
+                    if !changed.contains(&path) && references.mentions(rel_path) && !is_output(&path, &blob) {
+                        entries.push(entry(path, false, blob, true));
+                    }
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// The files under `root` to consider, with whether each is a deleted file and, when
+    /// read from a git commit, its blob.
+    fn list_files(&self, root: &Path) -> Result<Vec<(PathBuf, bool, Option<Blob>)>> {
+        let Some(changes) = &self.changes else {
+            return self.all_files(root);
+        };
+        let mut new_blobs: Option<HashMap<PathBuf, Blob>> = self.git_files(root)?.map(|f| f.into_iter().collect());
+        let mut files = Vec::new();
+        for change in git::changed_files(root, changes)? {
+            let path = root.join(&change.path);
+            if change.deleted {
+                files.push((path, true, None));
+                continue;
+            }
+            // Changed files missing from the working tree are tombstones too, unless read from
+            // git; other non-files, like symlinks and submodules, are left out.
+            match &mut new_blobs {
+                Some(blobs) => {
+                    if let Some(blob) = blobs.remove(&change.path) {
+                        files.push((path, false, Some(blob)));
+                    }
+                }
+                None if !path.exists() => files.push((path, true, None)),
+                None if path.is_file() => files.push((path, false, None)),
+                None => {}
+            }
+        }
+        Ok(files)
+    }
+
+    /// Every file under `root`, on disk or in git when [`reads_blobs`](Bundler::reads_blobs).
+    fn all_files(&self, root: &Path) -> Result<Vec<(PathBuf, bool, Option<Blob>)>> {
+        if let Some(files) = self.git_files(root)? {
+            return Ok(files.into_iter().map(|(path, blob)| (root.join(path), false, Some(blob))).collect());
+        }
+
+        let respect_gitignore = self.config.respect_gitignore;
+        Ok(WalkBuilder::new(root)
+            .hidden(false)
+            .parents(respect_gitignore)
+            .ignore(respect_gitignore)
+            .git_ignore(respect_gitignore)
+            .git_global(respect_gitignore)
+            .git_exclude(respect_gitignore)
+            // Git's own files are never bundled, whether or not ignore files are honored.
+            .filter_entry(|e| e.file_name() != ".git")
+            .build()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_some_and(|t| t.is_file()))
+            .map(|e| (e.into_path(), false, None))
+            .collect())
+    }
+
+    /// Whether file content is read from git objects rather than from disk.
+    fn reads_blobs(&self) -> bool {
+        self.revision.is_some()
+            || self.changes.as_ref().is_some_and(|changes| {
+                *changes == ChangeSet::Staged || changes.new_revision().is_some()
+            })
+    }
+
+    /// The files under `root` in git, relative to it, when [`reads_blobs`](Bundler::reads_blobs):
+    /// those in the tree of the revision, of the newer side of the changes, or in the index.
+    fn git_files(&self, root: &Path) -> Result<Option<Vec<(PathBuf, Blob)>>> {
+        if let Some(revision) = &self.revision {
+            return git::tree_files(root, revision).map(Some);
+        }
+        match &self.changes {
+            Some(ChangeSet::Staged) => git::index_files(root).map(Some),
+            Some(changes) => changes.new_revision().map(|revision| git::tree_files(root, revision)).transpose(),
+            None => Ok(None),
+        }
+    }
+
+    /// The roots added to the builder, or else those from the config.
+    pub(crate) fn effective_roots(&self) -> &[RootConfig] {
+        if self.roots.is_empty() { &self.config.roots } else { &self.roots }
+    }
+
+    /// Writes the bundle to the file at `path` atomically: to a temporary file next to it
+    /// that then replaces it, so readers never see a partial bundle and a failed run
+    /// leaves the previous one in place.
+    ///
+    /// The file itself is never bundled, should it be inside a root.
+    pub fn bundle_to_file(&self, path: &Path) -> Result<BundleSummary> {
+        let output = OutputFile::new(path)?;
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(&output.temp_prefix).suffix(".tmp");
+        // Like `File::create`, subject to the umask; temporary files default to owner-only.
+        #[cfg(unix)]
+        builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+        let mut temp = builder.tempfile_in(&output.dir).context("Failed to create output file")?;
+        let summary = self.write_bundle(BufWriter::new(temp.as_file_mut()), Some(&output))?;
+        temp.persist(path).context("Failed to replace output file")?;
+        Ok(summary)
+    }
+
+    /// Writes the bundle to `out` in the configured format.
+    ///
+    /// With `preamble` enabled, the files are spooled to a temporary file until the
+    /// table of contents is known.
+    pub fn bundle<W: Write>(&self, out: W) -> Result<BundleSummary> {
+        self.write_bundle(out, None)
+    }
+
+    fn write_bundle<W: Write>(&self, out: W, output: Option<&OutputFile>) -> Result<BundleSummary> {
+        let config = &self.config;
+        let pipeline = Pipeline {
+            binary_rules: BinaryRules::new(config.binary_policy, &config.binary_rules)?,
+            limits: SizeLimits {
+                max_file_bytes: config.max_file_bytes,
+                max_file_lines: config.max_file_lines,
+                strategy: config.truncation,
+            },
+            stream_threshold: config.stream_threshold_bytes,
+            line_endings: config.line_endings,
+            strip_comments: config.strip_comments,
+            header_fields: &config.header_fields,
+            language_rules: config
+                .language_rules
+                .iter()
+                .map(|rule| Ok((language::parse(&rule.language)?, rule)))
+                .collect::<Result<_>>()?,
+            blob_readers: match self.reads_blobs() {
+                true => self
+                    .effective_roots()
+                    .iter()
+                    .map(|root| Ok((root.path.clone(), BlobReader::new(&root.path)?)))
+                    .collect::<Result<_>>()?,
+                false => Vec::new(),
+            },
+        };
+        let mut byte_budget = ByteBudget::new(config.max_total_bytes);
+        let tokenizer = Tokenizer::new(config.tokenizer);
+        let mut budget = TokenBudget::new(config.max_tokens, config.token_budget_policy);
+        let mut summary = BundleSummary::default();
+
+        let options = WriterOptions { marker_style: config.marker_style };
+        let spool = Spool::new(out, config.preamble)?;
+        let mut writer = match &self.writer {
+            Some(writer) => writer(Box::new(spool.handle())),
+            None => self.format.unwrap_or(config.format).writer(spool.handle(), options),
+        };
+        writer.begin()?;
+        spool.section(Section::Body);
+
+        let walked = self.walk(output)?;
+        if config.tree {
+            let paths = walked
+                .iter()
+                .filter(|(entry, _)| !entry.deleted)
+                .map(|(entry, included)| (entry.rel_path.as_path(), *included));
+            writer.tree(&tree::render(paths, config.tree_excluded, config.tree_depth))?;
+        }
+        if let (true, Some(changes)) = (self.diff, &self.changes) {
+            let roots = self.effective_roots();
+            let mut diff = String::new();
+            for root in roots {
+                diff.push_str(&git::diff(&root.path, changes, root_label(root, roots.len()).as_deref())?);
+            }
+            writer.diff(&diff)?;
+        }
+        let entries = walked.into_iter().filter_map(|(entry, included)| included.then_some(entry));
+        let entries: Vec<_> = self.sorted(entries.collect())?.collect();
+
+        let jobs = config.jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+        parallel::ordered_map(
+            &entries,
+            jobs,
+            config.max_in_flight_bytes,
+            |entry| {
+                let stream = pipeline.stream_plan(entry);
+                let units = match (&stream, &entry.blob) {
+                    // Streamed files are never held in memory.
+                    (Ok(Some(_)), _) => 0,
+                    (_, Some(blob)) => blob.size,
+                    _ => fs::metadata(&entry.path).map_or(0, |meta| meta.len()),
+                };
+                (units, stream)
+            },
+            |entry, stream| {
+                let record = match pipeline.process_file(entry, stream?)? {
+                    Processed::File(record) => *record,
+                    Processed::Skipped => return Ok(None),
+                    Processed::OverLimit(size) => return Ok(Some(Err(size))),
+                };
+                let (tokens, lines) = match &record.content {
+                    FileContent::Stream { path, size, .. } => {
+                        (size.div_ceil(4) as usize, eol::count_file_lines(path)?)
+                    }
+                    content => (tokenizer.count(content.text()), eol::line_count(content.text().as_bytes())),
+                };
+                Ok(Some(Ok((record, tokens, lines))))
+            },
+            |entry, processed: Result<Option<Result<CountedRecord, u64>>>| {
+                let (record, tokens, lines) = match processed {
+                    Ok(Some(Ok(processed))) => processed,
+                    Ok(Some(Err(size))) => {
+                        summary.left_out_size.push((entry.rel_path.to_string_lossy().into_owned(), size));
+                        return Ok(());
+                    }
+                    Ok(None) => return Ok(()),
+                    Err(e) => {
+                        summary.warnings.push((entry.path.display().to_string(), format!("{:#}", e)));
+                        return Ok(());
+                    }
+                };
+
+                let bytes = record.content.byte_len();
+                if !byte_budget.fits(bytes) {
+                    byte_budget.leave_out(&record.path, bytes);
+                    return Ok(());
+                }
+                if budget.admit(&record.path, tokens) {
+                    byte_budget.add(bytes);
+                    writer.write_file(&record)?;
+                    let language = record.language;
+                    summary.files.push(FileStats { path: record.path, language, bytes, lines, tokens });
+                }
+                Ok(())
+            },
+        )?;
+        if config.preamble {
+            spool.section(Section::Preamble);
+            let preamble = Preamble::new(self.effective_roots(), self.revision.as_deref(), config, &summary.files)?;
+            writer.preamble(&preamble)?;
+        }
+        spool.section(Section::Tail);
+        writer.finish()?;
+        spool.finish()?;
+
+        summary.total_tokens = budget.used;
+        summary.total_bytes = byte_budget.used;
+        summary.left_out_bytes = byte_budget.left_out;
+        summary.left_out_tokens = budget.left_out;
+        Ok(summary)
+    }
+}
+
+/// The content of a text file, for finding its references; `None` for binary and unreadable files.
+fn read_text(entry: &BundleEntry, blobs: Option<&BlobReader>) -> Option<String> {
+    let bytes = match (&entry.blob, blobs) {
+        (Some(blob), Some(blobs)) => blobs.read(blob).ok()?,
+        _ => fs::read(&entry.path).ok()?,
+    };
+    (!binary::is_binary(&bytes)).then(|| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Detects the language of a file from its name and first bytes; binary and unreadable
+/// files have none, deleted files only the one their name implies.
+///
+/// Files from a git commit are read through `blobs`, which must be set for them.
+fn sniff_language(entry: &BundleEntry, blobs: Option<&BlobReader>) -> Option<&'static str> {
+    if entry.deleted {
+        return language::detect(&entry.rel_path, &[], false);
+    }
+    let mut prefix = Vec::with_capacity(binary::SNIFF_LEN);
+    match (&entry.blob, blobs) {
+        (Some(blob), Some(blobs)) => prefix = blobs.read(blob).ok()?,
+        _ => {
+            File::open(&entry.path).and_then(|f| f.take(binary::SNIFF_LEN as u64).read_to_end(&mut prefix)).ok()?;
+        }
+    }
+    if binary::is_binary(&prefix) {
+        return None;
+    }
+    let complete = prefix.len() < binary::SNIFF_LEN || entry.blob.is_some();
+    language::detect(&entry.rel_path, &prefix, complete)
+}
+
+/// A file the bundle is written to by [`Bundler::bundle_to_file`].
+pub(crate) struct OutputFile {
+    /// Canonical; the file itself may not exist yet.
+    pub dir: PathBuf,
+    pub name: OsString,
+    /// Names of the temporary files written before replacing the output start with this.
+    pub temp_prefix: String,
+}
+
+impl OutputFile {
+    pub fn new(path: &Path) -> Result<Self> {
+        let name = path.file_name().context("Output path has no file name")?.to_os_string();
+        let dir = parent_dir(path);
+        Ok(OutputFile {
+            dir: dir.canonicalize().context(format!("Output directory does not exist: {}", dir.display()))?,
+            temp_prefix: format!(".{}.", name.to_string_lossy()),
+            name,
+        })
+    }
+
+    /// Whether `path` is the output file or one of its temporary files.
+    pub fn matches(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else { return false };
+        (*name == *self.name || name.to_string_lossy().starts_with(&self.temp_prefix))
+            && parent_dir(path).canonicalize().is_ok_and(|dir| dir == self.dir)
+    }
+}
+
+/// The directory containing `path`, which is `.` for a bare file name.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// The root's label, or its directory name when several roots are bundled together.
+fn root_label(root: &RootConfig, roots: usize) -> Option<PathBuf> {
+    match &root.label {
+        Some(label) => Some(PathBuf::from(label)),
+        None if roots > 1 => Some(default_label(&root.path)),
+        None => None,
+    }
+}
+
+/// Falls back to the root's directory name, e.g. `services/api` -> `api`.
+fn default_label(root: &Path) -> PathBuf {
+    let name = root
+        .canonicalize()
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_os_string()))
+        .unwrap_or_else(|| root.as_os_str().to_os_string());
+    PathBuf::from(name)
+}
+
+/// Per-file settings shared by the worker threads.
+struct Pipeline<'a> {
+    binary_rules: BinaryRules,
+    limits: SizeLimits,
+    stream_threshold: u64,
+    line_endings: LineEndings,
+    strip_comments: StripComments,
+    header_fields: &'a [HeaderField],
+    /// `language_rules` with canonical language names.
+    language_rules: Vec<(&'static str, &'a LanguageRule)>,
+    /// One reader per root when bundling a revision.
+    blob_readers: Vec<(PathBuf, BlobReader)>,
+}
+
+/// What [`Pipeline::process_file`] made of a file.
+enum Processed {
+    File(Box<FileRecord>),
+    /// Deliberately left out, like binary files under the `skip` policy.
+    Skipped,
+    /// Over a per-file limit under the `skip` truncation strategy; holds the file's size.
+    OverLimit(u64),
+}
+
+impl From<FileRecord> for Processed {
+    fn from(record: FileRecord) -> Self {
+        Processed::File(Box::new(record))
+    }
+}
+
+/// What [`Pipeline::file_meta`] needs to know about a file besides its content.
+struct FileInfo {
+    size: u64,
+    mtime: Option<SystemTime>,
+    mode: Option<u32>,
+}
+
+impl FileInfo {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path).context("Failed to read file metadata")?;
+        Ok(FileInfo { size: metadata.len(), mtime: metadata.modified().ok(), mode: unix_mode(&metadata) })
+    }
+}
+
+/// A file on disk that [`Pipeline::stream_plan`] found can be streamed rather than read.
+struct StreamPlan {
+    info: FileInfo,
+    language: Option<&'static str>,
+    /// The file's layout when line endings are preserved.
+    layout: Option<TextLayout>,
+}
+
+impl Pipeline<'_> {
+    /// The size limits, line-ending mode and comment stripping for a file of `language`,
+    /// after applying every matching rule in order.
+    fn rules_for(&self, language: Option<&str>) -> (SizeLimits, LineEndings, StripComments) {
+        let mut limits = self.limits;
+        let mut line_endings = self.line_endings;
+        let mut strip_comments = self.strip_comments;
+        for (_, rule) in self.language_rules.iter().filter(|(name, _)| Some(*name) == language) {
+            if rule.max_file_bytes.is_some() {
+                limits.max_file_bytes = rule.max_file_bytes;
+            }
+            if rule.max_file_lines.is_some() {
+                limits.max_file_lines = rule.max_file_lines;
+            }
+            if let Some(truncation) = rule.truncation {
+                limits.strategy = truncation;
+            }
+            if let Some(rule_line_endings) = rule.line_endings {
+                line_endings = rule_line_endings;
+            }
+            if let Some(rule_strip_comments) = rule.strip_comments {
+                strip_comments = rule_strip_comments;
+            }
+        }
+        (limits, line_endings, strip_comments)
+    }
+
+    /// Reads and transforms one file.
+    ///
+    /// Files with a [`StreamPlan`] are not read here; the writer copies them in chunks and
+    /// their token count is estimated from their size.
+    ///
+    /// With [`LineEndings::Preserve`], text keeps its exact bytes and its layout is recorded;
+    /// text that is not valid UTF-8 is embedded as base64 so that nothing is lost. Comments
+    /// are stripped before the size limits apply; a truncated file's original size is still
+    /// that of the file as read.
+    fn process_file(&self, entry: &BundleEntry, stream: Option<StreamPlan>) -> Result<Processed> {
+        let path = entry.rel_path.to_string_lossy().into_owned();
+        if entry.deleted {
+            let language = language::detect(&entry.rel_path, &[], false);
+            let mut meta = FileMeta::default();
+            if self.header_fields.contains(&HeaderField::Language) {
+                meta.language = language;
+            }
+            let content = FileContent::Deleted;
+            return Ok(FileRecord { path, content, truncated: None, layout: None, language, meta }.into());
+        }
+        if let Some(StreamPlan { info, language, layout }) = stream {
+            let meta = self.file_meta(entry, &info, None, false, language)?;
+            let normalize = layout.is_none();
+            let content = FileContent::Stream { path: entry.path.clone(), size: info.size, normalize };
+            return Ok(FileRecord { path, content, truncated: None, layout, language, meta }.into());
+        }
+
+        let (info, bytes) = match &entry.blob {
+            Some(blob) => {
+                let info = FileInfo { size: blob.size, mtime: blob.mtime(), mode: Some(blob.mode) };
+                (info, self.read_blob(blob)?)
+            }
+            None => (FileInfo::of(&entry.path)?, fs::read(&entry.path).context("Failed to read file")?),
+        };
+
+        let is_binary = binary::is_binary(&bytes);
+        let language = if is_binary { None } else { language::detect(&entry.rel_path, &bytes, true) };
+        let (limits, line_endings, strip_comments) = self.rules_for(language);
+        let preserve = line_endings == LineEndings::Preserve;
+        let meta = self.file_meta(entry, &info, Some(&bytes), is_binary, language)?;
+
+        if is_binary {
+            let content = match self.binary_rules.policy_for(&entry.rel_path) {
+                BinaryPolicy::Skip => return Ok(Processed::Skipped),
+                BinaryPolicy::Base64 if !limits.exceeds_bytes(bytes.len() as u64) => {
+                    FileContent::Base64(binary::encode_base64(&bytes))
+                }
+                BinaryPolicy::Placeholder | BinaryPolicy::Base64 => FileContent::Omitted {
+                    reason: "binary",
+                    size: bytes.len() as u64,
+                    sha256: binary::sha256_hex(&bytes),
+                },
+            };
+            return Ok(FileRecord { path, content, truncated: None, layout: None, language, meta }.into());
+        }
+
+        let content = match String::from_utf8(bytes) {
+            Ok(text) if preserve => text,
+            Ok(text) => eol::normalize_lf(&text),
+            Err(e) if preserve && !limits.exceeds_bytes(e.as_bytes().len() as u64) => {
+                let content = FileContent::Base64(binary::encode_base64(e.as_bytes()));
+                return Ok(FileRecord { path, content, truncated: None, layout: None, language, meta }.into());
+            }
+            Err(e) => eol::normalize_lf(&String::from_utf8_lossy(e.as_bytes())),
+        };
+        let unstripped_lines = comments::applies(language, strip_comments).then(|| content.lines().count());
+        let content = comments::strip(&content, language, strip_comments).unwrap_or(content);
+
+        let (content, truncated) = match limits.apply(content) {
+            Limited::Unchanged(content) => (content, None),
+            Limited::Truncated(content, truncation) => {
+                // The original size is the file's, not that of the text left after stripping.
+                let original_lines = unstripped_lines.unwrap_or(truncation.original_lines);
+                (content, Some(Truncation { original_bytes: info.size, original_lines }))
+            }
+            Limited::Skipped => return Ok(Processed::OverLimit(info.size)),
+        };
+
+        let layout = if preserve { Some(TextLayout::detect(content.as_bytes())) } else { None };
+        Ok(FileRecord { path, content: FileContent::Text(content), truncated, layout, language, meta }.into())
+    }
+
+    /// How to stream the file at `entry` instead of reading it, if it is a text file on disk
+    /// over `stream_threshold` that needs no truncation and no comment stripping. Preserved
+    /// text must also be valid UTF-8 throughout, as otherwise it is embedded as base64.
+    fn stream_plan(&self, entry: &BundleEntry) -> Result<Option<StreamPlan>> {
+        if entry.deleted || entry.blob.is_some() {
+            return Ok(None);
+        }
+        let info = FileInfo::of(&entry.path)?;
+        if info.size <= self.stream_threshold {
+            return Ok(None);
+        }
+        let mut prefix = Vec::with_capacity(binary::SNIFF_LEN);
+        File::open(&entry.path)
+            .and_then(|f| f.take(binary::SNIFF_LEN as u64).read_to_end(&mut prefix))
+            .context("Failed to read file")?;
+        let language = language::detect(&entry.rel_path, &prefix, false);
+        let (limits, line_endings, strip_comments) = self.rules_for(language);
+        if limits.exceeds_bytes(info.size)
+            || limits.max_file_lines.is_some()
+            || comments::applies(language, strip_comments)
+            || binary::is_binary(&prefix)
+        {
+            return Ok(None);
+        }
+        let layout = if line_endings == LineEndings::Preserve {
+            match TextLayout::detect_file(&entry.path)? {
+                Some(layout) => Some(layout),
+                None => return Ok(None),
+            }
+        } else {
+            None
+        };
+        Ok(Some(StreamPlan { info, language, layout }))
+    }
+
+    fn read_blob(&self, blob: &Blob) -> Result<Vec<u8>> {
+        let (_, reader) = self
+            .blob_readers
+            .iter()
+            .find(|(repo, _)| *repo == blob.repo)
+            .context("No git reader for the file's repository")?;
+        reader.read(blob)
+    }
+
+    /// Collects the configured header fields, all describing the file as it is on disk, or
+    /// in the commit it is read from. `bytes` is the file's content if already read;
+    /// otherwise it is read in chunks as needed.
+    fn file_meta(
+        &self,
+        entry: &BundleEntry,
+        info: &FileInfo,
+        bytes: Option<&[u8]>,
+        is_binary: bool,
+        language: Option<&'static str>,
+    ) -> Result<FileMeta> {
+        let mut meta = FileMeta::default();
+        for field in self.header_fields {
+            match field {
+                HeaderField::Size => meta.size = Some(info.size),
+                HeaderField::Lines if is_binary => {}
+                HeaderField::Lines => {
+                    meta.lines = Some(match bytes {
+                        Some(bytes) => eol::line_count(bytes),
+                        None => eol::count_file_lines(&entry.path)?,
+                    })
+                }
+                HeaderField::Mtime => {
+                    meta.mtime = info
+                        .mtime
+                        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                        .map(|d| preamble::format_utc(d.as_secs() as i64))
+                }
+                HeaderField::Sha256 => {
+                    meta.sha256 = Some(match bytes {
+                        Some(bytes) => binary::sha256_hex(bytes),
+                        None => binary::sha256_file(&entry.path)?,
+                    })
+                }
+                HeaderField::Language => meta.language = language,
+                HeaderField::Mode => meta.mode = info.mode,
+            }
+        }
+        Ok(meta)
+    }
+}
+
+#[cfg(unix)]
+fn unix_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn unix_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unbundle::parse_bundle;
+
+    /// Bundles `dir` as text with `config` and returns each file's path and decoded content.
+    fn round_trip(dir: &Path, config: Config) -> Vec<(String, Vec<u8>)> {
+        let mut out = Vec::new();
+        Bundler::new().root(dir).config(config).format(OutputFormat::Text).bundle(&mut out).unwrap();
+        let files = parse_bundle(&String::from_utf8(out).unwrap()).unwrap();
+        files.iter().map(|file| (file.path.clone(), file.decoded_content().unwrap())).collect()
+    }
+
+    /// Writes one line per file: its path and content size.
+    struct Listing<'a> {
+        out: Box<dyn Write + 'a>,
+    }
+
+    impl BundleWriter for Listing<'_> {
+        fn write_file(&mut self, file: &FileRecord) -> Result<()> {
+            Ok(writeln!(self.out, "{} {}", file.path, file.content.byte_len())?)
+        }
+
+        fn preamble(&mut self, _: &Preamble) -> Result<()> {
+            Ok(())
+        }
+
+        fn tree(&mut self, _: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn diff(&mut self, _: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn custom_writers_render_the_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "bb\n").unwrap();
+        let mut out = Vec::new();
+        Bundler::new().root(dir.path()).writer(|out| Box::new(Listing { out })).bundle(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a.txt 2\nb.txt 3\n");
+    }
+
+    #[test]
+    fn preserved_files_over_the_stream_threshold_keep_invalid_utf8() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = "line of text\r\n".repeat(900).into_bytes();
+        content[6000] = 0xE9;
+        fs::write(dir.path().join("latin1.txt"), &content).unwrap();
+        let valid = "a\r\nb".repeat(3000);
+        fs::write(dir.path().join("valid.txt"), &valid).unwrap();
+
+        let config = Config { line_endings: LineEndings::Preserve, stream_threshold_bytes: 1000, ..Config::default() };
+        let files = round_trip(dir.path(), config);
+        assert_eq!(files, vec![("latin1.txt".to_string(), content), ("valid.txt".to_string(), valid.into_bytes())]);
+    }
+}
@@ -0,0 +1,712 @@
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+
+/// Which comments to remove from source files before bundling.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StripComments {
+    /// Keep files as they are.
+    #[default]
+    Off,
+    /// Remove every line and block comment.
+    All,
+    /// Remove comments but keep doc comments: `///`, `//!`, `/** */` and `/*! */`.
+    KeepDocs,
+}
+
+impl StripComments {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "off" => Ok(StripComments::Off),
+            "all" => Ok(StripComments::All),
+            "keep_docs" => Ok(StripComments::KeepDocs),
+            _ => Err(anyhow::anyhow!("Unknown comment stripping mode: {} (expected off, all or keep_docs)", name)),
+        }
+    }
+}
+
+/// Whether [`strip`] changes files of `language` in `mode`.
+pub fn applies(language: Option<&str>, mode: StripComments) -> bool {
+    mode != StripComments::Off && language.and_then(syntax).is_some()
+}
+
+/// Removes the comments from `text`, written in `language`; `None` when [`applies`] is false.
+///
+/// Supported are Rust, the C family (C, C++, C#, Java, Go, Kotlin, Swift, Scala), JavaScript
+/// and TypeScript, Python, shell, SQL, YAML and TOML. String literals, heredocs, YAML block
+/// scalars and shebang lines are left alone. Lines that only held comments are dropped,
+/// as is the whitespace before a trailing comment; other lines keep their line endings.
+pub fn strip(text: &str, language: Option<&str>, mode: StripComments) -> Option<String> {
+    if mode == StripComments::Off {
+        return None;
+    }
+    let syntax = syntax(language?)?;
+    let stripper = Stripper {
+        text,
+        syntax: &syntax,
+        keep_docs: mode == StripComments::KeepDocs,
+        pos: 0,
+        out: String::with_capacity(text.len()),
+        line: String::new(),
+        comment_at: None,
+        last_code: None,
+        heredocs: Vec::new(),
+    };
+    Some(stripper.run())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// `\"`
+    Backslash,
+    /// `''` in SQL and YAML, `""` in C# verbatim strings.
+    Doubled,
+}
+
+struct Quote {
+    open: &'static str,
+    close: &'static str,
+    escape: Escape,
+    /// Whether the literal may span lines; otherwise it ends at an unescaped line break.
+    multiline: bool,
+}
+
+const fn quote(open: &'static str, escape: Escape, multiline: bool) -> Quote {
+    Quote { open, close: open, escape, multiline }
+}
+
+/// Syntax that needs more than comment markers and quotes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Dialect {
+    Plain,
+    /// Char literals next to lifetimes, raw strings `r#"..."#`.
+    Rust,
+    /// Raw strings `R"delim(...)delim"`.
+    Cpp,
+    /// Regex literals.
+    JavaScript,
+    /// Backslash escapes outside quotes, heredocs.
+    Shell,
+    /// Dollar-quoted strings `$tag$...$tag$`.
+    Sql,
+    /// Block scalars, quotes only at the start of a scalar.
+    Yaml,
+}
+
+struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    nested_blocks: bool,
+    /// Comment openers kept with [`StripComments::KeepDocs`].
+    doc_comments: &'static [&'static str],
+    /// Longer openers come first.
+    quotes: &'static [Quote],
+    /// Line comments only start a word, as `#` in shell and YAML.
+    word_start_comments: bool,
+    dialect: Dialect,
+}
+
+const C_DOCS: &[&str] = &["///", "//!", "/**", "/*!"];
+
+const RUST_QUOTES: &[Quote] = &[quote("\"", Escape::Backslash, true)];
+const C_QUOTES: &[Quote] = &[quote("\"", Escape::Backslash, false), quote("'", Escape::Backslash, false)];
+const TRIPLE_QUOTES: &[Quote] = &[
+    quote("\"\"\"", Escape::None, true),
+    quote("\"", Escape::Backslash, false),
+    quote("'", Escape::Backslash, false),
+];
+const CSHARP_QUOTES: &[Quote] = &[
+    quote("\"\"\"", Escape::None, true),
+    Quote { open: "@\"", close: "\"", escape: Escape::Doubled, multiline: true },
+    quote("\"", Escape::Backslash, false),
+    quote("'", Escape::Backslash, false),
+];
+const GO_QUOTES: &[Quote] =
+    &[quote("`", Escape::None, true), quote("\"", Escape::Backslash, false), quote("'", Escape::Backslash, false)];
+const JS_QUOTES: &[Quote] = &[
+    quote("`", Escape::Backslash, true),
+    quote("\"", Escape::Backslash, false),
+    quote("'", Escape::Backslash, false),
+];
+const PYTHON_QUOTES: &[Quote] = &[
+    quote("\"\"\"", Escape::Backslash, true),
+    quote("'''", Escape::Backslash, true),
+    quote("\"", Escape::Backslash, false),
+    quote("'", Escape::Backslash, false),
+];
+const SHELL_QUOTES: &[Quote] = &[quote("'", Escape::None, true), quote("\"", Escape::Backslash, true)];
+const YAML_QUOTES: &[Quote] = &[quote("'", Escape::Doubled, true), quote("\"", Escape::Backslash, true)];
+const TOML_QUOTES: &[Quote] = &[
+    quote("\"\"\"", Escape::Backslash, true),
+    quote("'''", Escape::None, true),
+    quote("\"", Escape::Backslash, false),
+    quote("'", Escape::None, false),
+];
+const SQL_QUOTES: &[Quote] = &[quote("'", Escape::Doubled, true), quote("\"", Escape::Doubled, true)];
+
+fn syntax(language: &str) -> Option<Syntax> {
+    let c_like = |quotes: &'static [Quote], nested_blocks: bool, dialect: Dialect| Syntax {
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        nested_blocks,
+        doc_comments: C_DOCS,
+        quotes,
+        word_start_comments: false,
+        dialect,
+    };
+    let hash = |quotes: &'static [Quote], word_start_comments: bool, dialect: Dialect| Syntax {
+        line_comments: &["#"],
+        block_comment: None,
+        nested_blocks: false,
+        doc_comments: &[],
+        quotes,
+        word_start_comments,
+        dialect,
+    };
+    Some(match language {
+        "rust" => c_like(RUST_QUOTES, true, Dialect::Rust),
+        "c" => c_like(C_QUOTES, false, Dialect::Plain),
+        "cpp" => c_like(C_QUOTES, false, Dialect::Cpp),
+        "csharp" => c_like(CSHARP_QUOTES, false, Dialect::Plain),
+        "java" => c_like(TRIPLE_QUOTES, false, Dialect::Plain),
+        "kotlin" | "swift" | "scala" => c_like(TRIPLE_QUOTES, true, Dialect::Plain),
+        "go" => c_like(GO_QUOTES, false, Dialect::Plain),
+        "javascript" | "typescript" | "jsx" | "tsx" => {
+            Syntax { doc_comments: &["/**"], ..c_like(JS_QUOTES, false, Dialect::JavaScript) }
+        }
+        "python" => hash(PYTHON_QUOTES, false, Dialect::Plain),
+        "bash" => hash(SHELL_QUOTES, true, Dialect::Shell),
+        "yaml" => hash(YAML_QUOTES, true, Dialect::Yaml),
+        "toml" => hash(TOML_QUOTES, false, Dialect::Plain),
+        "sql" => Syntax {
+            line_comments: &["--"],
+            block_comment: Some(("/*", "*/")),
+            nested_blocks: false,
+            doc_comments: &[],
+            quotes: SQL_QUOTES,
+            word_start_comments: false,
+            dialect: Dialect::Sql,
+        },
+        _ => return None,
+    })
+}
+
+/// Copies `text` to `out` line by line, leaving out the comments.
+struct Stripper<'a> {
+    text: &'a str,
+    syntax: &'a Syntax,
+    keep_docs: bool,
+    pos: usize,
+    out: String,
+    /// The current line, without its line break.
+    line: String,
+    /// Length of `line` where the last comment on it was removed; `Some(0)` for a line
+    /// inside a block comment.
+    comment_at: Option<usize>,
+    /// The last non-whitespace character copied, to tell regex literals from division.
+    last_code: Option<char>,
+    /// Delimiters of the heredocs started on the current line, and whether their
+    /// lines may be indented with tabs (`<<-`).
+    heredocs: Vec<(String, bool)>,
+}
+
+impl<'a> Stripper<'a> {
+    fn run(mut self) -> String {
+        if self.syntax.line_comments.contains(&"#") && self.text.starts_with("#!") {
+            self.copy(self.text.find('\n').unwrap_or(self.text.len()));
+        }
+        while let Some(c) = self.text[self.pos..].chars().next() {
+            let rest = &self.text[self.pos..];
+            if c == '\n' {
+                let block_scalar = self.syntax.dialect == Dialect::Yaml && opens_block_scalar(&self.line);
+                let indent = self.line.len() - self.line.trim_start_matches(' ').len();
+                self.newline();
+                self.copy_heredocs();
+                if block_scalar {
+                    self.copy_block_scalar(indent);
+                }
+            } else if let Some(len) = self.line_comment(rest) {
+                match self.keep_docs && is_doc(rest, self.syntax.doc_comments) {
+                    true => self.copy(len),
+                    false => {
+                        self.comment_at = Some(self.line.len());
+                        self.pos += len;
+                    }
+                }
+            } else if let Some(len) = self.block_comment(rest) {
+                match self.keep_docs && is_doc(rest, self.syntax.doc_comments) {
+                    true => self.copy(len),
+                    false => self.remove_block(len),
+                }
+            } else if let Some(len) = self.literal(rest) {
+                self.copy(len);
+            } else {
+                self.copy(c.len_utf8());
+            }
+        }
+        if !self.line.is_empty() || self.comment_at.is_some() {
+            self.finish_line("");
+        }
+        self.out
+    }
+
+    /// The length of the line comment at the start of `rest`, up to the line break.
+    fn line_comment(&self, rest: &str) -> Option<usize> {
+        self.syntax.line_comments.iter().find(|marker| rest.starts_with(*marker))?;
+        if self.syntax.word_start_comments && !self.at_word_start() {
+            return None;
+        }
+        Some(rest.find('\n').unwrap_or(rest.len()))
+    }
+
+    fn block_comment(&self, rest: &str) -> Option<usize> {
+        let (open, close) = self.syntax.block_comment?;
+        if !rest.starts_with(open) {
+            return None;
+        }
+        let mut depth = 0;
+        let mut i = 0;
+        while i < rest.len() {
+            if rest[i..].starts_with(open) && (depth == 0 || self.syntax.nested_blocks) {
+                depth += 1;
+                i += open.len();
+            } else if rest[i..].starts_with(close) {
+                depth -= 1;
+                i += close.len();
+                if depth == 0 {
+                    return Some(i);
+                }
+            } else {
+                i += char_len(&rest[i..]);
+            }
+        }
+        Some(rest.len())
+    }
+
+    /// The length of the string, char or regex literal, heredoc marker or escaped
+    /// character at the start of `rest`, which is copied as is.
+    fn literal(&mut self, rest: &str) -> Option<usize> {
+        let prev = self.prev_char();
+        let dialect_len = match self.syntax.dialect {
+            Dialect::Rust if rest.starts_with('\'') => Some(rust_char(rest)),
+            Dialect::Rust if !prev.is_some_and(is_ident_char) => rust_raw_string(rest),
+            Dialect::Cpp if !prev.is_some_and(is_ident_char) || matches!(prev, Some('L' | 'u' | 'U' | '8')) => {
+                cpp_raw_string(rest)
+            }
+            Dialect::JavaScript if rest.starts_with('/') && self.regex_allowed() => regex(rest),
+            Dialect::Shell if rest.starts_with('\\') => Some(1 + rest[1..].chars().next().map_or(0, char::len_utf8)),
+            Dialect::Shell => heredoc(rest).map(|(len, delimiter, tabs)| {
+                self.heredocs.push((delimiter, tabs));
+                len
+            }),
+            Dialect::Sql if rest.starts_with('$') => dollar_quote(rest),
+            Dialect::Yaml if !prev.is_none_or(|c| c.is_whitespace() || "[{,".contains(c)) => return None,
+            _ => None,
+        };
+        if dialect_len.is_some() {
+            return dialect_len;
+        }
+        let quote = self.syntax.quotes.iter().find(|quote| rest.starts_with(quote.open))?;
+        Some(quoted_len(rest, quote))
+    }
+
+    fn prev_char(&self) -> Option<char> {
+        self.text[..self.pos].chars().next_back()
+    }
+
+    fn at_word_start(&self) -> bool {
+        self.prev_char().is_none_or(|c| {
+            c.is_whitespace() || (self.syntax.dialect == Dialect::Shell && ";&|()".contains(c))
+        })
+    }
+
+    /// After these, a `/` starts a regex rather than a division.
+    fn regex_allowed(&self) -> bool {
+        let trimmed = self.line.trim_end();
+        self.last_code.is_none_or(|c| "(,=:[!&|?{};+-*%<>~^".contains(c))
+            || ["return", "typeof", "case"].iter().any(|keyword| {
+                trimmed.strip_suffix(keyword).is_some_and(|before| !before.ends_with(is_ident_char))
+            })
+    }
+
+    /// Copies the next `len` bytes, line breaks included.
+    fn copy(&mut self, len: usize) {
+        let end = self.pos + len;
+        while self.pos < end {
+            let c = self.text[self.pos..].chars().next().unwrap_or_default();
+            if c == '\n' {
+                self.newline();
+                continue;
+            }
+            if !c.is_whitespace() {
+                self.last_code = Some(c);
+            }
+            self.line.push(c);
+            self.pos += c.len_utf8();
+        }
+    }
+
+    /// Skips the block comment of `len` bytes, keeping a space between the code around it.
+    fn remove_block(&mut self, len: usize) {
+        let end = self.pos + len;
+        self.comment_at = Some(self.line.len());
+        while let Some(i) = self.text[self.pos..end].find('\n') {
+            self.pos += i;
+            self.newline();
+            self.comment_at = Some(0);
+        }
+        self.pos = end;
+        let next = self.text[self.pos..].chars().next();
+        match self.line.chars().next_back() {
+            None | Some(' ' | '\t') => {
+                let rest = &self.text[self.pos..];
+                self.pos += rest.len() - rest.trim_start_matches([' ', '\t']).len();
+            }
+            Some(_) if next.is_some_and(|c| !c.is_whitespace()) => self.line.push(' '),
+            Some(_) => {}
+        }
+    }
+
+    /// Ends the line at the `\n` at `pos`.
+    fn newline(&mut self) {
+        let crlf = self.text[..self.pos].ends_with('\r');
+        self.finish_line(if crlf { "\r\n" } else { "\n" });
+        self.pos += 1;
+    }
+
+    fn finish_line(&mut self, line_break: &str) {
+        if self.line.ends_with('\r') {
+            self.line.pop();
+        }
+        if let Some(at) = self.comment_at.take() {
+            if at >= self.line.len() {
+                self.line.truncate(self.line.trim_end_matches([' ', '\t']).len());
+            }
+            if self.line.trim().is_empty() {
+                self.line.clear();
+                return;
+            }
+        }
+        self.out.push_str(&self.line);
+        self.out.push_str(line_break);
+        self.line.clear();
+    }
+
+    /// Copies the bodies of the heredocs started on the line just ended.
+    fn copy_heredocs(&mut self) {
+        for (delimiter, tabs) in std::mem::take(&mut self.heredocs) {
+            while self.pos < self.text.len() {
+                let line = self.copy_raw_line();
+                let line = if tabs { line.trim_start_matches('\t') } else { line };
+                if line == delimiter {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Copies the lines of a YAML block scalar, which are indented more than the line
+    /// that opened it, or blank.
+    fn copy_block_scalar(&mut self, indent: usize) {
+        while let Some(line) = self.text[self.pos..].split_inclusive('\n').next() {
+            let line = line.trim_end_matches(['\n', '\r']);
+            if !line.trim().is_empty() && line.len() - line.trim_start_matches(' ').len() <= indent {
+                break;
+            }
+            self.copy_raw_line();
+        }
+    }
+
+    /// Copies the line at `pos` unchanged and returns it without its line break.
+    fn copy_raw_line(&mut self) -> &'a str {
+        let text = self.text;
+        let start = self.pos;
+        let end = text[start..].find('\n').map_or(text.len(), |i| start + i + 1);
+        self.out.push_str(&text[start..end]);
+        self.pos = end;
+        text[start..end].trim_end_matches(['\n', '\r'])
+    }
+}
+
+fn char_len(s: &str) -> usize {
+    s.chars().next().map_or(1, char::len_utf8)
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// `///` and `/**` are doc comments, `////` and `/***` or `/**/` are not.
+fn is_doc(rest: &str, doc_comments: &[&str]) -> bool {
+    doc_comments.iter().any(|marker| {
+        let Some(after) = rest.strip_prefix(marker) else { return false };
+        let last = marker.chars().next_back();
+        !after.starts_with(|c| Some(c) == last || (*marker == "/**" && c == '/'))
+    })
+}
+
+/// The length of the string starting with `quote.open`, or up to the end of the line or
+/// text when it is not closed.
+fn quoted_len(rest: &str, quote: &Quote) -> usize {
+    let mut i = quote.open.len();
+    while i < rest.len() {
+        let tail = &rest[i..];
+        if let Some(after) = tail.strip_prefix(quote.close) {
+            if quote.escape == Escape::Doubled && after.starts_with(quote.close) {
+                i += 2 * quote.close.len();
+                continue;
+            }
+            return i + quote.close.len();
+        }
+        if tail.starts_with('\n') && !quote.multiline {
+            return i;
+        }
+        if tail.starts_with('\\') && quote.escape == Escape::Backslash {
+            i += 1 + tail[1..].chars().next().map_or(0, char::len_utf8);
+            continue;
+        }
+        i += char_len(tail);
+    }
+    rest.len()
+}
+
+/// `r"..."`, `r#"..."#`, `br"..."`.
+fn rust_raw_string(rest: &str) -> Option<usize> {
+    let after = rest.strip_prefix("br").or_else(|| rest.strip_prefix('r'))?;
+    let hashes = after.len() - after.trim_start_matches('#').len();
+    let body = after[hashes..].strip_prefix('"')?;
+    let close = format!("\"{}", "#".repeat(hashes));
+    let start = rest.len() - body.len();
+    Some(body.find(&close).map_or(rest.len(), |i| start + i + close.len()))
+}
+
+/// `'a'` and `'\n'` are char literals; in `'a` and `<'static>` the quote starts a lifetime.
+fn rust_char(rest: &str) -> usize {
+    let mut chars = rest[1..].chars();
+    match (chars.next(), chars.next()) {
+        (Some('\\'), _) => quoted_len(rest, &quote("'", Escape::Backslash, false)),
+        (Some(c), Some('\'')) if c != '\'' => 2 + c.len_utf8(),
+        _ => 1,
+    }
+}
+
+/// `R"(...)"`, `R"delim(...)delim"`.
+fn cpp_raw_string(rest: &str) -> Option<usize> {
+    let after = rest.strip_prefix("R\"")?;
+    let paren = after.find('(')?;
+    let delimiter = &after[..paren];
+    if delimiter.len() > 16 || delimiter.contains(|c: char| c.is_whitespace() || c == ')' || c == '\\') {
+        return None;
+    }
+    let close = format!("){}\"", delimiter);
+    let start = 2 + paren + 1;
+    Some(rest[start..].find(&close).map_or(rest.len(), |i| start + i + close.len()))
+}
+
+/// `/ab+c/gi`, with `/` allowed inside `[...]`; `None` when no regex closes on the line.
+fn regex(rest: &str) -> Option<usize> {
+    let mut in_class = false;
+    let mut chars = rest.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\n' => return None,
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `<<EOF`, `<<-EOF`, `<< 'EOF'` or `<<"EOF"`: the marker's length, the delimiter and
+/// whether its lines may be indented with tabs. `<<<` starts a here-string instead.
+fn heredoc(rest: &str) -> Option<(usize, String, bool)> {
+    let after = rest.strip_prefix("<<")?;
+    if after.starts_with('<') {
+        return None;
+    }
+    let (tabs, after) = match after.strip_prefix('-') {
+        Some(after) => (true, after),
+        None => (false, after),
+    };
+    let spec = after.trim_start_matches([' ', '\t']);
+    let (delimiter, len) = match spec.chars().next()? {
+        q @ ('\'' | '"') => {
+            let end = spec[1..].find(q)?;
+            (&spec[1..1 + end], end + 2)
+        }
+        c if c.is_ascii_alphabetic() || c == '_' => {
+            let end = spec.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(spec.len());
+            (&spec[..end], end)
+        }
+        _ => return None,
+    };
+    if delimiter.is_empty() || delimiter.contains('\n') {
+        return None;
+    }
+    Some((rest.len() - spec.len() + len, delimiter.to_string(), tabs))
+}
+
+/// `$$...$$` or `$body$...$body$`, as in PostgreSQL; `$1` is a parameter.
+fn dollar_quote(rest: &str) -> Option<usize> {
+    let end = rest[1..].find('$')? + 1;
+    let tag = &rest[..=end];
+    if !tag[1..end].chars().all(is_ident_char) || tag[1..end].starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let start = tag.len();
+    Some(rest[start..].find(tag).map_or(rest.len(), |i| start + i + tag.len()))
+}
+
+/// `key: |`, `- >-` or `|2+`: the line starts a block scalar.
+fn opens_block_scalar(line: &str) -> bool {
+    let code = line.trim_end();
+    let indicator = code.trim_end_matches(|c: char| c == '+' || c == '-' || c.is_ascii_digit());
+    let indicators = code.len() - indicator.len();
+    let Some(before) = indicator.strip_suffix(['|', '>']) else { return false };
+    indicators <= 2 && (before.is_empty() || before.ends_with(char::is_whitespace))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_all(text: &str, language: &str) -> String {
+        strip(text, Some(language), StripComments::All).unwrap()
+    }
+
+    fn strip_keep_docs(text: &str, language: &str) -> String {
+        strip(text, Some(language), StripComments::KeepDocs).unwrap()
+    }
+
+    #[test]
+    fn off_and_unknown_languages_are_left_alone() {
+        assert!(strip("// c\n", Some("rust"), StripComments::Off).is_none());
+        assert!(strip("<!-- c -->\n", Some("html"), StripComments::All).is_none());
+        assert!(strip("// c\n", None, StripComments::All).is_none());
+        assert!(!applies(Some("markdown"), StripComments::All));
+        assert!(applies(Some("rust"), StripComments::KeepDocs));
+    }
+
+    #[test]
+    fn drops_comment_lines_and_trailing_comments() {
+        let text = "// header\nfn main() { // call\n    run();\n}\n";
+        assert_eq!(strip_all(text, "rust"), "fn main() {\n    run();\n}\n");
+    }
+
+    #[test]
+    fn keeps_blank_lines_that_held_no_comment() {
+        assert_eq!(strip_all("a();\n\n// c\nb();\n", "c"), "a();\n\nb();\n");
+    }
+
+    #[test]
+    fn leaves_comment_markers_in_strings() {
+        let text = "let s = \"// not /* a */ comment\"; // real\n";
+        assert_eq!(strip_all(text, "rust"), "let s = \"// not /* a */ comment\";\n");
+        let text = "char *s = \"a \\\" // b\"; /* c */\n";
+        assert_eq!(strip_all(text, "c"), "char *s = \"a \\\" // b\";\n");
+    }
+
+    #[test]
+    fn block_comments_keep_code_apart() {
+        assert_eq!(strip_all("a = 1/*x*/+2;\n", "c"), "a = 1 +2;\n");
+        assert_eq!(strip_all("    /* c */ x();\n", "c"), "    x();\n");
+        assert_eq!(strip_all("x(); /* a\n b\n */ y();\nz();\n", "c"), "x();\ny();\nz();\n");
+    }
+
+    #[test]
+    fn rust_block_comments_nest() {
+        assert_eq!(strip_all("/* a /* b */ still */ let x = 1;\n", "rust"), "let x = 1;\n");
+        // C block comments do not nest.
+        assert_eq!(strip_all("/* a /* b */ int x;\n", "c"), "int x;\n");
+    }
+
+    #[test]
+    fn rust_chars_lifetimes_and_raw_strings() {
+        let text = "fn f<'a>(s: &'a str) -> char { '\"' } // c\nlet q = '\\''; // c\n";
+        assert_eq!(strip_all(text, "rust"), "fn f<'a>(s: &'a str) -> char { '\"' }\nlet q = '\\'';\n");
+        let text = "let r = r#\"raw \"// string\"#; // c\nlet b = br\"/* x */\";\n";
+        assert_eq!(strip_all(text, "rust"), "let r = r#\"raw \"// string\"#;\nlet b = br\"/* x */\";\n");
+    }
+
+    #[test]
+    fn keep_docs_keeps_doc_comments_only() {
+        let text = "//! Crate.\n// plain\n/// Item.\n//// not a doc\n/** Block. */\n/**/ /*** banner */\nfn f() {}\n";
+        assert_eq!(strip_keep_docs(text, "rust"), "//! Crate.\n/// Item.\n/** Block. */\nfn f() {}\n");
+        assert_eq!(strip_all(text, "rust"), "fn f() {}\n");
+        assert_eq!(strip_keep_docs("/** JSDoc */\n// c\nf();\n", "javascript"), "/** JSDoc */\nf();\n");
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let text = "a = 1  # c\r\n# only a comment\r\nb = 2\r\n";
+        assert_eq!(strip_all(text, "python"), "a = 1\r\nb = 2\r\n");
+        assert_eq!(strip_all("x();\r\n/* a\r\n b */\r\ny();", "c"), "x();\r\ny();");
+    }
+
+    #[test]
+    fn drops_a_final_comment_without_newline() {
+        assert_eq!(strip_all("x = 1\n# end", "python"), "x = 1\n");
+    }
+
+    #[test]
+    fn cpp_raw_strings() {
+        let text = "auto s = R\"x(// )\" /* )x\"; // c\n";
+        assert_eq!(strip_all(text, "cpp"), "auto s = R\"x(// )\" /* )x\";\n");
+    }
+
+    #[test]
+    fn csharp_verbatim_and_go_raw_strings() {
+        assert_eq!(strip_all("var s = @\"a \"\" // b\"; // c\n", "csharp"), "var s = @\"a \"\" // b\";\n");
+        assert_eq!(strip_all("s := `a\n// b` // c\n", "go"), "s := `a\n// b`\n");
+    }
+
+    #[test]
+    fn javascript_regexes_templates_and_division() {
+        let text = "const re = /\\/\\/[/*]x/g; // c\nconst d = a / b / c; // c\nconst t = `// ${x}`;\n";
+        let expected = "const re = /\\/\\/[/*]x/g;\nconst d = a / b / c;\nconst t = `// ${x}`;\n";
+        assert_eq!(strip_all(text, "typescript"), expected);
+    }
+
+    #[test]
+    fn python_keeps_shebang_and_docstrings() {
+        let text =
+            "#!/usr/bin/env python3\n# c\ndef f():\n    \"\"\"Doc # not a comment.\"\"\"\n    return '#'  # c\n";
+        let expected = "#!/usr/bin/env python3\ndef f():\n    \"\"\"Doc # not a comment.\"\"\"\n    return '#'\n";
+        assert_eq!(strip_all(text, "python"), expected);
+    }
+
+    #[test]
+    fn shell_comments_start_words_and_skip_heredocs() {
+        let text = "echo \"#a\" '#b' $# ${#x[@]} a#b \\# # c\ncat <<-EOF\n\t# kept\n\tEOF\necho ok # c\n";
+        let expected = "echo \"#a\" '#b' $# ${#x[@]} a#b \\#\ncat <<-EOF\n\t# kept\n\tEOF\necho ok\n";
+        assert_eq!(strip_all(text, "bash"), expected);
+        assert_eq!(strip_all("cat <<'END' # c\n# kept\nEND\n", "bash"), "cat <<'END'\n# kept\nEND\n");
+    }
+
+    #[test]
+    fn sql_strings_and_dollar_quotes() {
+        let text = "-- c\nSELECT 'it''s -- no', /* c */ x -- c\nFROM t; $$ -- body $$\n";
+        assert_eq!(strip_all(text, "sql"), "SELECT 'it''s -- no', x\nFROM t; $$ -- body $$\n");
+        assert_eq!(strip_all("SELECT $1 -- c\n", "sql"), "SELECT $1\n");
+    }
+
+    #[test]
+    fn yaml_quotes_and_block_scalars() {
+        let text =
+            "# c\na: 'it''s # no' # c\nb: don't # c\nc: x#y\nrun: |\n  echo # kept\n\n  y # kept\nd: \"# no\" # c\n";
+        let expected = "a: 'it''s # no'\nb: don't\nc: x#y\nrun: |\n  echo # kept\n\n  y # kept\nd: \"# no\"\n";
+        assert_eq!(strip_all(text, "yaml"), expected);
+    }
+
+    #[test]
+    fn toml_strings() {
+        let text = "# c\na = \"x # y\" # c\nb = 'c:\\p' # c\nc = \"\"\"\n# kept\n\"\"\"\n";
+        assert_eq!(strip_all(text, "toml"), "a = \"x # y\"\nb = 'c:\\p'\nc = \"\"\"\n# kept\n\"\"\"\n");
+    }
+}
@@ -0,0 +1,328 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result};
+use crate::binary::{BinaryPolicy, BinaryRule};
+use crate::comments::StripComments;
+use crate::eol::LineEndings;
+use crate::language::LanguageRule;
+use crate::limits::TruncationStrategy;
+use crate::order::FileOrder;
+use crate::output::{HeaderField, MarkerStyle, OutputFormat};
+use crate::tokens::{BudgetPolicy, TokenizerKind};
+
+/// File names looked up by [`Config::discover`], in order of preference.
+pub const CONFIG_FILE_NAMES: &[&str] = &[".file_bundler.yaml", "file_bundler.yaml"];
+
+/// Starting point written by `file_bundler init`.
+pub const DEFAULT_CONFIG_TEMPLATE: &str = r#"# file_bundler configuration
+
+# Input directories used when none are given on the command line. Each root may
+# carry a label (its path prefix in the bundle) and its own include/exclude rules:
+# its excludes apply on top of the global ones, and its includes add to them.
+# roots:
+#   - path: services/api
+#     label: api
+#     exclude_dirs: [fixtures]
+#   - path: services/web
+
+# Directories, exact files and glob patterns (relative to each root) to leave out.
+exclude_dirs:
+  - target
+  - node_modules
+exclude_files: []
+exclude_patterns:
+  - "*.lock"
+
+# When set, only matching files are bundled. Excludes still win over includes.
+include_dirs: []
+include_patterns: []
+
+# Languages are detected from modelines, file names and shebangs, e.g. rust, python,
+# bash, toml. Files of excluded languages are left out; when include_languages is
+# set, only files of those languages are bundled.
+include_languages: []
+exclude_languages: []
+
+# Per-language overrides of max_file_bytes, max_file_lines, truncation, line_endings
+# and strip_comments.
+language_rules: []
+#   - language: markdown
+#     max_file_lines: 200
+#     truncation: head
+
+# Honor .gitignore, .ignore, .git/info/exclude and the global git excludes file.
+# The .git directory itself is never bundled.
+respect_gitignore: true
+
+# Binary files: skip, placeholder (size + sha256) or base64.
+binary_policy: placeholder
+binary_rules: []
+
+# File order: path, dirs_first, size or mtime. Files matching `priority` (relative to
+# their root) come first.
+order: path
+priority:
+  - README.md
+  - Cargo.toml
+
+# text, markdown, json, jsonl or xml.
+format: text
+
+# preserve keeps each file's exact bytes (CRLF, missing final newline) and records
+# them in the header; lf converts line endings to \n and adds a final newline.
+line_endings: preserve
+
+# Remove comments from Rust, C-family, JS/TS, Python, shell, SQL, YAML and TOML files,
+# leaving strings alone: off, all, or keep_docs to keep doc comments (///, /** */).
+strip_comments: off
+
+# How text bundles stay parseable when a file contains the marker lines: auto adds a
+# length=N attribute to such files, length adds it to every file, and boundary adds a
+# random per-bundle boundary to every marker.
+marker_style: auto
+
+# Extra per-file header fields, in every format: size, lines, mtime, sha256,
+# language and mode (unix permissions).
+header_fields: []
+
+# Start the bundle with a preamble: format and generator version, roots, timestamp
+# (SOURCE_DATE_EPOCH if set), this config and a table of contents.
+preamble: false
+
+# Start the bundle with an ASCII tree of the included files, optionally with the
+# excluded ones marked, down to tree_depth levels.
+tree: false
+tree_excluded: false
+# tree_depth: 3
+
+# Per-file limits and what to do with files over them: skip, head or head_tail.
+# max_file_bytes: 1048576
+# max_file_lines: 5000
+truncation: skip
+# max_total_bytes: 10485760
+
+# cl100k, o200k or estimate (chars/4).
+tokenizer: cl100k
+# max_tokens: 100000
+token_budget_policy: stop
+
+# Text files above this size are streamed to the output instead of read into memory.
+stream_threshold_bytes: 8388608
+
+# Parallel reading: number of threads (default: CPU count) and the most bytes
+# read ahead of the writer.
+# jobs: 8
+max_in_flight_bytes: 67108864
+"#;
+
+/// An input directory with an optional label and rules that apply only to it.
+///
+/// The root's rules combine with the global ones: a file is left out if an exclude rule
+/// of either matches, and, when either has include rules, kept only if an include rule
+/// of either matches.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RootConfig {
+    /// Relative paths are resolved against the directory containing the config file.
+    pub path: PathBuf,
+    /// Prefix for this root's paths in the bundle. Defaults to the directory name
+    /// when several roots are bundled together.
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub exclude_dirs: Vec<String>,
+    #[serde(default)]
+    pub exclude_files: Vec<String>,
+    #[serde(default)]
+    pub exclude_patterns: Vec<String>,
+    #[serde(default)]
+    pub include_dirs: Vec<String>,
+    #[serde(default)]
+    pub include_patterns: Vec<String>,
+}
+
+impl RootConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        RootConfig { path: path.into(), ..RootConfig::default() }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Config {
+    /// Input directories used when none are given on the command line or to the [`Bundler`](crate::Bundler).
+    #[serde(default)]
+    pub roots: Vec<RootConfig>,
+    /// Rules below apply to every root, matched against paths relative to that root.
+    #[serde(default)]
+    pub exclude_dirs: Vec<String>,
+    #[serde(default)]
+    pub exclude_files: Vec<String>,
+    #[serde(default)]
+    pub exclude_patterns: Vec<String>,
+    /// When any include rule is set, only files matching one of them are bundled.
+    /// Exclude rules always take precedence over include rules.
+    #[serde(default)]
+    pub include_dirs: Vec<String>,
+    #[serde(default)]
+    pub include_patterns: Vec<String>,
+    /// Languages to bundle exclusively, or to leave out; see [`language::detect`](crate::language::detect).
+    #[serde(default)]
+    pub include_languages: Vec<String>,
+    #[serde(default)]
+    pub exclude_languages: Vec<String>,
+    /// Settings that override the global ones for files of a language.
+    #[serde(default)]
+    pub language_rules: Vec<LanguageRule>,
+    /// What to do with files detected as binary; `binary_rules` override it per file.
+    #[serde(default)]
+    pub binary_policy: BinaryPolicy,
+    #[serde(default)]
+    pub binary_rules: Vec<BinaryRule>,
+    /// Order of files in the bundle: path, dirs_first, size or mtime.
+    #[serde(default)]
+    pub order: FileOrder,
+    /// Glob patterns for files pinned to the top of the bundle, in the listed order.
+    #[serde(default)]
+    pub priority: Vec<String>,
+    /// Output format; overridden by `--format` on the command line.
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// Keep original line endings and final newlines, or normalize text to LF.
+    #[serde(default)]
+    pub line_endings: LineEndings,
+    /// Remove comments from source files of supported languages, optionally keeping doc comments.
+    #[serde(default)]
+    pub strip_comments: StripComments,
+    /// How the text format delimits content that contains its own marker lines.
+    #[serde(default)]
+    pub marker_style: MarkerStyle,
+    /// Extra fields in each file's header: size, lines, mtime, sha256, language and mode.
+    #[serde(default)]
+    pub header_fields: Vec<HeaderField>,
+    /// Start the bundle with metadata, the config used and a table of contents.
+    #[serde(default)]
+    pub preamble: bool,
+    /// Start the bundle with a directory tree of the included files.
+    #[serde(default)]
+    pub tree: bool,
+    /// Also show excluded files in the tree, marked as such.
+    #[serde(default)]
+    pub tree_excluded: bool,
+    /// Directories deeper than this are collapsed into a file count.
+    #[serde(default)]
+    pub tree_depth: Option<usize>,
+    /// Per-file limits; over-limit text files are handled according to `truncation`.
+    #[serde(default)]
+    pub max_file_bytes: Option<u64>,
+    #[serde(default)]
+    pub max_file_lines: Option<usize>,
+    #[serde(default)]
+    pub truncation: TruncationStrategy,
+    /// Cap on the total content bytes in the bundle; files beyond it are left out and reported.
+    #[serde(default)]
+    pub max_total_bytes: Option<u64>,
+    /// Tokenizer used for per-file and total token counts.
+    #[serde(default)]
+    pub tokenizer: TokenizerKind,
+    /// Token budget for the whole bundle; files beyond it are left out and reported.
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub token_budget_policy: BudgetPolicy,
+    /// Print the token count of every bundled file.
+    #[serde(default)]
+    pub token_report: bool,
+    /// Text files larger than this are copied to the output in chunks instead of being
+    /// read into memory, unless they need truncating. Their token counts are estimated.
+    #[serde(default = "default_stream_threshold_bytes")]
+    pub stream_threshold_bytes: u64,
+    /// Number of threads reading and transforming files; defaults to the number of CPUs.
+    #[serde(default)]
+    pub jobs: Option<usize>,
+    /// Upper bound on the bytes of files read but not yet written to the bundle.
+    #[serde(default = "default_max_in_flight_bytes")]
+    pub max_in_flight_bytes: u64,
+    /// Honor .gitignore, .ignore, .git/info/exclude and the global git excludes file.
+    /// The `.git` directory is skipped either way.
+    #[serde(default = "default_true")]
+    pub respect_gitignore: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            roots: Vec::new(),
+            exclude_dirs: Vec::new(),
+            exclude_files: Vec::new(),
+            exclude_patterns: Vec::new(),
+            include_dirs: Vec::new(),
+            include_patterns: Vec::new(),
+            include_languages: Vec::new(),
+            exclude_languages: Vec::new(),
+            language_rules: Vec::new(),
+            binary_policy: BinaryPolicy::default(),
+            binary_rules: Vec::new(),
+            order: FileOrder::default(),
+            priority: Vec::new(),
+            format: OutputFormat::default(),
+            line_endings: LineEndings::default(),
+            strip_comments: StripComments::default(),
+            marker_style: MarkerStyle::default(),
+            header_fields: Vec::new(),
+            preamble: false,
+            tree: false,
+            tree_excluded: false,
+            tree_depth: None,
+            max_file_bytes: None,
+            max_file_lines: None,
+            truncation: TruncationStrategy::default(),
+            max_total_bytes: None,
+            tokenizer: TokenizerKind::default(),
+            max_tokens: None,
+            token_budget_policy: BudgetPolicy::default(),
+            token_report: false,
+            stream_threshold_bytes: default_stream_threshold_bytes(),
+            jobs: None,
+            max_in_flight_bytes: default_max_in_flight_bytes(),
+            respect_gitignore: true,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the YAML config at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let config_str = fs::read_to_string(path).context("Failed to read config file")?;
+        let mut config: Config = serde_yaml::from_str(&config_str).context("Failed to parse config file")?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        for root in &mut config.roots {
+            root.path = base.join(&root.path);
+        }
+        Ok(config)
+    }
+
+    /// Looks for a config file in `start` and each of its ancestors.
+    pub fn discover(start: &Path) -> Option<PathBuf> {
+        let start = start.canonicalize().ok()?;
+        start.ancestors().find_map(|dir| {
+            CONFIG_FILE_NAMES.iter().map(|name| dir.join(name)).find(|path| path.is_file())
+        })
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_stream_threshold_bytes() -> u64 {
+    8 * 1024 * 1024
+}
+
+fn default_max_in_flight_bytes() -> u64 {
+    64 * 1024 * 1024
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+    /// Keep the original bytes so an unbundle is byte-identical.
+    #[default]
+    Preserve,
+    /// Convert `\r\n` to `\n` and make sure every file ends with a newline.
+    Lf,
+}
+
+impl LineEndings {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "preserve" => Ok(LineEndings::Preserve),
+            "lf" => Ok(LineEndings::Lf),
+            _ => Err(anyhow::anyhow!("Unknown line ending mode: {} (expected preserve or lf)", name)),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EolStyle {
+    Lf,
+    Crlf,
+    Cr,
+    Mixed,
+    /// The content has no line breaks at all.
+    None,
+}
+
+impl fmt::Display for EolStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EolStyle::Lf => "lf",
+            EolStyle::Crlf => "crlf",
+            EolStyle::Cr => "cr",
+            EolStyle::Mixed => "mixed",
+            EolStyle::None => "none",
+        })
+    }
+}
+
+/// Line-ending style and final-newline presence of a preserved text file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TextLayout {
+    pub eol: EolStyle,
+    pub final_newline: bool,
+}
+
+impl TextLayout {
+    pub fn detect(bytes: &[u8]) -> Self {
+        let mut scan = EolScan::default();
+        scan.feed(bytes);
+        scan.finish()
+    }
+
+    /// Detects the layout of the file at `path` without holding it in memory, or returns
+    /// `None` if the file is not valid UTF-8 from start to end.
+    pub fn detect_file(path: &Path) -> Result<Option<Self>> {
+        let mut file = File::open(path).context("Failed to open file")?;
+        let mut buf = vec![0; 64 * 1024];
+        let mut scan = EolScan::default();
+        // Bytes of a multi-byte character split across reads, kept at the front of `buf`.
+        let mut carried = 0;
+        loop {
+            let n = file.read(&mut buf[carried..]).context("Failed to read file")?;
+            if n == 0 {
+                return Ok((carried == 0).then(|| scan.finish()));
+            }
+            scan.feed(&buf[carried..carried + n]);
+            let filled = carried + n;
+            carried = match std::str::from_utf8(&buf[..filled]) {
+                Ok(_) => 0,
+                Err(e) if e.error_len().is_none() => filled - e.valid_up_to(),
+                Err(_) => return Ok(None),
+            };
+            buf.copy_within(filled - carried..filled, 0);
+        }
+    }
+
+    /// Whether this is the layout bundles assume when nothing is recorded: LF with a final newline.
+    pub fn is_default(&self) -> bool {
+        matches!(self.eol, EolStyle::Lf | EolStyle::None) && self.final_newline
+    }
+}
+
+/// Line-break counts over bytes that may arrive in several chunks.
+#[derive(Default)]
+struct EolScan {
+    lf: bool,
+    crlf: bool,
+    cr: bool,
+    pending_cr: bool,
+    last: Option<u8>,
+}
+
+impl EolScan {
+    fn feed(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if self.pending_cr {
+                self.pending_cr = false;
+                if b == b'\n' {
+                    self.crlf = true;
+                    continue;
+                }
+                self.cr = true;
+            }
+            match b {
+                b'\r' => self.pending_cr = true,
+                b'\n' => self.lf = true,
+                _ => {}
+            }
+        }
+        if let Some(&b) = bytes.last() {
+            self.last = Some(b);
+        }
+    }
+
+    fn finish(mut self) -> TextLayout {
+        if self.pending_cr {
+            self.cr = true;
+        }
+        let eol = match (self.lf, self.crlf, self.cr) {
+            (false, false, false) => EolStyle::None,
+            (true, false, false) => EolStyle::Lf,
+            (false, true, false) => EolStyle::Crlf,
+            (false, false, true) => EolStyle::Cr,
+            _ => EolStyle::Mixed,
+        };
+        // A file ending in a lone `\r` counts as having no final newline: bundle formats
+        // only know `\n` as the line terminator that separates content from the end marker.
+        TextLayout { eol, final_newline: self.last.is_none_or(|b| b == b'\n') }
+    }
+}
+
+/// Rewrites `text` with `\n` line endings and a trailing newline.
+pub fn normalize_lf(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len() + 1);
+    for line in text.lines() {
+        normalized.push_str(line);
+        normalized.push('\n');
+    }
+    normalized
+}
+
+/// Number of lines in `bytes`, counting an unterminated last line.
+pub fn line_count(bytes: &[u8]) -> usize {
+    let newlines = bytes.iter().filter(|&&b| b == b'\n').count();
+    newlines + usize::from(bytes.last().is_some_and(|&b| b != b'\n'))
+}
+
+/// [`line_count`] of the file at `path`, read in chunks.
+pub fn count_file_lines(path: &Path) -> Result<usize> {
+    let mut file = File::open(path).context("Failed to open file")?;
+    let mut buf = vec![0; 64 * 1024];
+    let (mut lines, mut last) = (0, None);
+    loop {
+        let n = file.read(&mut buf).context("Failed to read file")?;
+        if n == 0 {
+            return Ok(lines + usize::from(last.is_some_and(|b| b != b'\n')));
+        }
+        lines += buf[..n].iter().filter(|&&b| b == b'\n').count();
+        last = Some(buf[n - 1]);
+    }
+}
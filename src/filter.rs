@@ -0,0 +1,94 @@
+use std::path::Path;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use anyhow::{Context, Result};
+use crate::config::{Config, RootConfig};
+
+/// Compiled include/exclude rules from a [`Config`] or [`RootConfig`].
+pub struct Filters {
+    exclude_dirs: Vec<String>,
+    exclude_files: Vec<String>,
+    exclude_patterns: GlobSet,
+    include_dirs: Vec<String>,
+    include_patterns: GlobSet,
+    has_includes: bool,
+}
+
+impl Filters {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Filters::new(
+            &config.exclude_dirs,
+            &config.exclude_files,
+            &config.exclude_patterns,
+            &config.include_dirs,
+            &config.include_patterns,
+        )
+    }
+
+    /// The rules for files of `root`: its own rules on top of the global ones. Excludes from
+    /// either list apply; a root's includes add to the global includes, so a file is
+    /// included when it matches an include rule of either.
+    pub fn for_root(config: &Config, root: &RootConfig) -> Result<Self> {
+        let merged = |global: &[String], own: &[String]| [global, own].concat();
+        Filters::new(
+            &merged(&config.exclude_dirs, &root.exclude_dirs),
+            &merged(&config.exclude_files, &root.exclude_files),
+            &merged(&config.exclude_patterns, &root.exclude_patterns),
+            &merged(&config.include_dirs, &root.include_dirs),
+            &merged(&config.include_patterns, &root.include_patterns),
+        )
+    }
+
+    fn new(
+        exclude_dirs: &[String],
+        exclude_files: &[String],
+        exclude_patterns: &[String],
+        include_dirs: &[String],
+        include_patterns: &[String],
+    ) -> Result<Self> {
+        Ok(Filters {
+            exclude_dirs: exclude_dirs.to_vec(),
+            exclude_files: exclude_files.to_vec(),
+            exclude_patterns: build_globset(exclude_patterns)?,
+            include_dirs: include_dirs.to_vec(),
+            include_patterns: build_globset(include_patterns)?,
+            has_includes: !include_dirs.is_empty() || !include_patterns.is_empty(),
+        })
+    }
+}
+
+fn build_globset(patterns: &[String]) -> Result<GlobSet> {
+    let mut glob_builder = GlobSetBuilder::new();
+    for pat in patterns {
+        glob_builder.add(Glob::new(pat).context(format!("Invalid glob pattern: {}", pat))?);
+    }
+    glob_builder.build().context("Failed to build globset")
+}
+
+fn in_dir(rel_str: &str, dir: &str) -> bool {
+    let prefix = format!("{}/", dir);
+    rel_str.starts_with(&prefix)
+}
+
+/// Decides whether the entry at `rel_path` (relative to its root) is left out of the bundle.
+pub fn should_skip(rel_path: &Path, is_dir: bool, filters: &Filters) -> bool {
+    let rel_str = rel_path.to_string_lossy();
+
+    if is_dir {
+        filters.exclude_dirs.iter().any(|dir| rel_str == dir.as_str())
+    } else {
+        let is_in_excluded_dir = filters.exclude_dirs.iter().any(|dir| in_dir(&rel_str, dir));
+        let is_excluded = is_in_excluded_dir ||
+            filters.exclude_files.iter().any(|file| rel_str == file.as_str()) ||
+            filters.exclude_patterns.is_match(rel_path);
+        if is_excluded {
+            return true;
+        }
+
+        if !filters.has_includes {
+            return false;
+        }
+        let is_included = filters.include_dirs.iter().any(|dir| in_dir(&rel_str, dir)) ||
+            filters.include_patterns.is_match(rel_path);
+        !is_included
+    }
+}
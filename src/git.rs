@@ -0,0 +1,336 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Output, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use anyhow::{Context, Result};
+
+/// Which git changes to select files from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChangeSet {
+    /// Changes between two revisions (`main..HEAD`, or `main...HEAD` for those since their
+    /// merge base), or between one revision and the working tree (`main`), as in `git diff`.
+    Revisions(String),
+    /// Uncommitted changes, staged or not, including untracked files that are not ignored.
+    WorkingTree,
+    /// Changes staged in the index, read from the index rather than the working tree.
+    Staged,
+}
+
+impl ChangeSet {
+    /// Parses `worktree`, `staged`, or anything else as revisions for `git diff`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        match spec {
+            "worktree" => Ok(ChangeSet::WorkingTree),
+            "staged" => Ok(ChangeSet::Staged),
+            "" => Err(anyhow::anyhow!("Empty revision (expected worktree, staged or revisions such as main...HEAD)")),
+            _ if spec.starts_with('-') => Err(anyhow::anyhow!("Invalid revision: {}", spec)),
+            _ => Ok(ChangeSet::Revisions(spec.to_string())),
+        }
+    }
+
+    /// The revision on the newer side of a range, like `HEAD` for `main..`; `None` when
+    /// the newer side is the working tree or the index.
+    pub fn new_revision(&self) -> Option<&str> {
+        let ChangeSet::Revisions(spec) = self else { return None };
+        let (_, new) = spec.split_once("...").or_else(|| spec.split_once(".."))?;
+        Some(if new.is_empty() { "HEAD" } else { new })
+    }
+
+    /// Whether the newer side of the comparison is the working tree, where untracked files count as added.
+    fn compares_working_tree(&self) -> bool {
+        *self != ChangeSet::Staged && self.new_revision().is_none()
+    }
+
+    /// The `git diff` arguments selecting the two sides.
+    fn diff_args(&self) -> &str {
+        match self {
+            ChangeSet::Revisions(spec) => spec,
+            ChangeSet::WorkingTree => "HEAD",
+            ChangeSet::Staged => "--cached",
+        }
+    }
+}
+
+/// A file that differs between the two sides of a [`ChangeSet`].
+pub struct Change {
+    /// Relative to the directory the changes were listed in.
+    pub path: PathBuf,
+    /// Deleted on the newer side. Renames are reported as a deletion plus an addition.
+    pub deleted: bool,
+}
+
+/// Lists the files below `dir` that changed according to `changes`, in the git
+/// repository containing `dir`.
+pub fn changed_files(dir: &Path, changes: &ChangeSet) -> Result<Vec<Change>> {
+    check_work_tree(dir)?;
+    let output = git(dir, &["diff", "--name-status", "-z", "--no-renames", "--relative", changes.diff_args(), "--"])?;
+
+    // `-z` output alternates status and path: `M\0src/lib.rs\0D\0old.rs\0`.
+    let mut changes_found = Vec::new();
+    let mut fields = output.split(|&b| b == 0).filter(|field| !field.is_empty());
+    while let (Some(status), Some(path)) = (fields.next(), fields.next()) {
+        changes_found.push(Change { path: path_from_bytes(path), deleted: status == b"D" });
+    }
+
+    if changes.compares_working_tree() {
+        let untracked = git(dir, &["ls-files", "--others", "--exclude-standard", "-z"])?;
+        changes_found.extend(
+            untracked
+                .split(|&b| b == 0)
+                .filter(|path| !path.is_empty())
+                .map(|path| Change { path: path_from_bytes(path), deleted: false }),
+        );
+    }
+    Ok(changes_found)
+}
+
+/// The unified diff of `changes` below `dir`, with paths relative to `dir` and prefixed
+/// with `label`, so that they match the paths in the bundle. Untracked files are not in it.
+pub fn diff(dir: &Path, changes: &ChangeSet, label: Option<&Path>) -> Result<String> {
+    check_work_tree(dir)?;
+    let label = label.map_or(String::new(), |label| format!("{}/", label.display()));
+    let src_prefix = format!("--src-prefix=a/{}", label);
+    let dst_prefix = format!("--dst-prefix=b/{}", label);
+    let args = [
+        "diff",
+        "--no-color",
+        "--no-ext-diff",
+        "--no-textconv",
+        "--relative",
+        &src_prefix,
+        &dst_prefix,
+        changes.diff_args(),
+        "--",
+    ];
+    Ok(String::from_utf8_lossy(&git(dir, &args)?).into_owned())
+}
+
+/// Outside a repository, `git diff` would silently compare paths instead.
+fn check_work_tree(dir: &Path) -> Result<()> {
+    git(dir, &["rev-parse", "--is-inside-work-tree"])
+        .map(|_| ())
+        .map_err(|_| anyhow::anyhow!("Not inside a git work tree: {}", dir.display()))
+}
+
+/// A file in a commit's tree, read from the object database instead of from disk.
+#[derive(Clone, Debug)]
+pub struct Blob {
+    /// Directory the tree was listed in; any directory of the repository.
+    pub repo: PathBuf,
+    pub oid: String,
+    pub size: u64,
+    /// Permission bits from the tree entry: 0644, or 0755 for executables.
+    pub mode: u32,
+    /// Commit time in seconds since the Unix epoch, used as the file's mtime. For a file
+    /// read from the index, the time the index was last written.
+    pub commit_time: i64,
+}
+
+impl Blob {
+    /// The commit time as a modification time.
+    pub fn mtime(&self) -> Option<SystemTime> {
+        let secs = u64::try_from(self.commit_time).ok()?;
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+}
+
+/// Lists the files below `dir` in the tree of commit `revision` (a hash, tag or branch),
+/// with paths relative to `dir`. Symlinks and submodules are left out, as on disk.
+pub fn tree_files(dir: &Path, revision: &str) -> Result<Vec<(PathBuf, Blob)>> {
+    if revision.is_empty() || revision.starts_with('-') {
+        return Err(anyhow::anyhow!("Invalid revision: {}", revision));
+    }
+    let commit = git(dir, &["rev-parse", "--verify", "--end-of-options", &format!("{}^{{commit}}", revision)])
+        .map_err(|_| anyhow::anyhow!("Not a commit in the git repository of {}: {}", dir.display(), revision))?;
+    let commit = String::from_utf8_lossy(&commit).trim().to_string();
+    let commit_time = String::from_utf8_lossy(&git(dir, &["show", "-s", "--format=%ct", &commit])?)
+        .trim()
+        .parse()
+        .context("Failed to read the commit time")?;
+
+    // Records look like `100644 blob <oid>     <size>\t<path>\0`; the listing is limited
+    // to `dir` and relative to it.
+    let listing = git(dir, &["ls-tree", "-r", "-l", "-z", &commit])?;
+    let mut files = Vec::new();
+    for record in listing.split(|&b| b == 0).filter(|record| !record.is_empty()) {
+        let tab = record.iter().position(|&b| b == b'\t').context("Malformed git ls-tree output")?;
+        let info = String::from_utf8_lossy(&record[..tab]);
+        let [mode, kind, oid, size] = info.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(anyhow::anyhow!("Malformed git ls-tree output: {}", info));
+        };
+        let mode = u32::from_str_radix(mode, 8).context("Malformed git ls-tree output")?;
+        if kind != "blob" || mode & 0o170000 != 0o100000 {
+            continue;
+        }
+        let blob = Blob {
+            repo: dir.to_path_buf(),
+            oid: oid.to_string(),
+            size: size.parse().context("Malformed git ls-tree output")?,
+            mode: mode & 0o7777,
+            commit_time,
+        };
+        files.push((path_from_bytes(&record[tab + 1..]), blob));
+    }
+    Ok(files)
+}
+
+/// Lists the files below `dir` as staged in the index, with paths relative to `dir`.
+/// Symlinks, submodules and unmerged paths are left out, as in [`tree_files`].
+pub fn index_files(dir: &Path) -> Result<Vec<(PathBuf, Blob)>> {
+    check_work_tree(dir)?;
+    // The index records no commit time; the time it was last written stands in for one.
+    let index = git(dir, &["rev-parse", "--git-path", "index"])?;
+    let commit_time = fs::metadata(dir.join(path_from_bytes(index.trim_ascii_end())))
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64);
+
+    // Records look like `100644 <oid> <stage>\t<path>\0`, relative to `dir`.
+    let listing = git(dir, &["ls-files", "--stage", "-z"])?;
+    let mut staged = Vec::new();
+    for record in listing.split(|&b| b == 0).filter(|record| !record.is_empty()) {
+        let tab = record.iter().position(|&b| b == b'\t').context("Malformed git ls-files output")?;
+        let info = String::from_utf8_lossy(&record[..tab]);
+        let [mode, oid, stage] = info.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(anyhow::anyhow!("Malformed git ls-files output: {}", info));
+        };
+        let mode = u32::from_str_radix(mode, 8).context("Malformed git ls-files output")?;
+        if stage != "0" || mode & 0o170000 != 0o100000 {
+            continue;
+        }
+        staged.push((path_from_bytes(&record[tab + 1..]), oid.to_string(), mode & 0o7777));
+    }
+
+    // The index has no sizes; one `git cat-file --batch-check` looks them all up.
+    let oids: String = staged.iter().map(|(_, oid, _)| format!("{}\n", oid)).collect();
+    let sizes = git_with_input(dir, &["cat-file", "--batch-check"], oids.into_bytes())?;
+    let mut sizes = sizes.split(|&b| b == b'\n');
+    let mut files = Vec::with_capacity(staged.len());
+    for (path, oid, mode) in staged {
+        // `<oid> blob <size>`, in the order the objects were asked for.
+        let header = String::from_utf8_lossy(sizes.next().unwrap_or_default());
+        let size = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse().context("Malformed git cat-file output")?,
+            _ => return Err(anyhow::anyhow!("Failed to read blob {}: {}", oid, header.trim())),
+        };
+        files.push((path, Blob { repo: dir.to_path_buf(), oid, size, mode, commit_time }));
+    }
+    Ok(files)
+}
+
+/// Reads blobs through one long-running `git cat-file --batch`, shared between threads.
+pub struct BlobReader {
+    process: Mutex<BatchProcess>,
+}
+
+struct BatchProcess {
+    child: Child,
+    /// Dropped to end the process.
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl BlobReader {
+    /// Starts the reader for the repository containing `dir`.
+    pub fn new(dir: &Path) -> Result<Self> {
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to run git")?;
+        let stdin = child.stdin.take();
+        let stdout = BufReader::new(child.stdout.take().context("Failed to open git cat-file output")?);
+        Ok(BlobReader { process: Mutex::new(BatchProcess { child, stdin, stdout }) })
+    }
+
+    pub fn read(&self, blob: &Blob) -> Result<Vec<u8>> {
+        let mut process = self.process.lock().map_err(|_| anyhow::anyhow!("git cat-file reader poisoned"))?;
+        let process = &mut *process;
+        let stdin = process.stdin.as_mut().context("git cat-file has exited")?;
+        writeln!(stdin, "{}", blob.oid).and_then(|_| stdin.flush()).context("Failed to write to git cat-file")?;
+
+        // `<oid> blob <size>\n<content>\n`, or `<oid> missing\n`.
+        let mut header = String::new();
+        process.stdout.read_line(&mut header).context("Failed to read from git cat-file")?;
+        let size = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse::<usize>().context("Malformed git cat-file output")?,
+            _ => return Err(anyhow::anyhow!("Failed to read blob {}: {}", blob.oid, header.trim())),
+        };
+        let mut content = vec![0; size + 1];
+        process.stdout.read_exact(&mut content).context("Failed to read from git cat-file")?;
+        content.pop();
+        Ok(content)
+    }
+}
+
+impl Drop for BlobReader {
+    fn drop(&mut self) {
+        if let Ok(process) = self.process.get_mut() {
+            process.stdin.take();
+            let _ = process.child.wait();
+        }
+    }
+}
+
+/// Runs `git` in `dir` and returns its standard output.
+fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+    check_output(args, dir, output)
+}
+
+/// Runs `git` in `dir` with `input` on its standard input and returns its standard output.
+fn git_with_input(dir: &Path, args: &[&str], input: Vec<u8>) -> Result<Vec<u8>> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run git")?;
+    // Written from another thread, so that a full output pipe cannot block the input.
+    let mut stdin = child.stdin.take().context("Failed to open git input")?;
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().context("Failed to run git")?;
+    writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to write to git"))?
+        .context("Failed to write to git")?;
+    check_output(args, dir, output)
+}
+
+fn check_output(args: &[&str], dir: &Path, output: Output) -> Result<Vec<u8>> {
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git {} failed in {}: {}",
+            args[0],
+            dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
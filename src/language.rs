@@ -0,0 +1,213 @@
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use crate::comments::StripComments;
+use crate::eol::LineEndings;
+use crate::limits::TruncationStrategy;
+
+/// Lines at each end of a file searched for modelines, as in vim's default.
+const MODELINE_LINES: usize = 5;
+
+/// Settings that override the global ones for files of one language.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LanguageRule {
+    pub language: String,
+    #[serde(default)]
+    pub max_file_bytes: Option<u64>,
+    #[serde(default)]
+    pub max_file_lines: Option<usize>,
+    #[serde(default)]
+    pub truncation: Option<TruncationStrategy>,
+    #[serde(default)]
+    pub line_endings: Option<LineEndings>,
+    #[serde(default)]
+    pub strip_comments: Option<StripComments>,
+}
+
+/// The `include_languages` and `exclude_languages` lists, with canonical names.
+pub struct LanguageFilter {
+    include: Vec<&'static str>,
+    exclude: Vec<&'static str>,
+}
+
+impl LanguageFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(LanguageFilter {
+            include: include.iter().map(|name| parse(name)).collect::<Result<_>>()?,
+            exclude: exclude.iter().map(|name| parse(name)).collect::<Result<_>>()?,
+        })
+    }
+
+    /// Whether any language rule is set, i.e. whether files need to be sniffed at all.
+    pub fn is_active(&self) -> bool {
+        !self.include.is_empty() || !self.exclude.is_empty()
+    }
+
+    /// Excludes win; with includes set, files of unknown language are left out.
+    pub fn allows(&self, language: Option<&str>) -> bool {
+        match language {
+            Some(language) if self.exclude.contains(&language) => false,
+            Some(language) => self.include.is_empty() || self.include.contains(&language),
+            None => self.include.is_empty(),
+        }
+    }
+}
+
+/// Detects the language of the file at `path` whose content starts with `content`.
+///
+/// A vim or emacs modeline wins over the file name, which wins over a shebang line.
+/// Modelines are looked for in the first and, if `complete` (i.e. `content` is the whole
+/// file), the last five lines. Names are lowercase and double as Markdown fence tags.
+pub fn detect(path: &Path, content: &[u8], complete: bool) -> Option<&'static str> {
+    let text = String::from_utf8_lossy(content);
+    let head = text.lines().take(MODELINE_LINES);
+    let tail = text.lines().rev().take(if complete { MODELINE_LINES } else { 0 });
+    head.chain(tail)
+        .find_map(modeline)
+        .or_else(|| from_path(path))
+        .or_else(|| text.lines().next().and_then(shebang))
+}
+
+/// Maps a language name or common alias (e.g. `py`, `c++`, `sh`) to its canonical name.
+pub fn canonical(name: &str) -> Option<&'static str> {
+    let name = name.to_ascii_lowercase();
+    Some(match name.as_str() {
+        "rust" | "rs" => "rust",
+        "python" | "py" => "python",
+        "javascript" | "js" | "node" | "nodejs" => "javascript",
+        "typescript" | "ts" => "typescript",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "c" => "c",
+        "cpp" | "c++" => "cpp",
+        "csharp" | "cs" | "c#" => "csharp",
+        "go" | "golang" => "go",
+        "java" => "java",
+        "kotlin" | "kt" => "kotlin",
+        "swift" => "swift",
+        "ruby" | "rb" => "ruby",
+        "php" => "php",
+        "perl" | "pl" => "perl",
+        "lua" => "lua",
+        "bash" | "sh" | "zsh" | "ksh" | "dash" | "shell" | "shell-script" => "bash",
+        "fish" => "fish",
+        "powershell" | "ps1" | "pwsh" => "powershell",
+        "haskell" | "hs" => "haskell",
+        "scala" => "scala",
+        "elixir" | "ex" => "elixir",
+        "r" => "r",
+        "sql" => "sql",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "xml" => "xml",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "ini" | "dosini" | "conf" => "ini",
+        "markdown" | "md" => "markdown",
+        "dockerfile" | "docker" => "dockerfile",
+        "makefile" | "make" => "makefile",
+        "cmake" => "cmake",
+        "nix" => "nix",
+        "text" | "txt" => "text",
+        _ => return None,
+    })
+}
+
+/// Like [`canonical`], but an error for unknown names; used to validate config lists.
+pub fn parse(name: &str) -> Result<&'static str> {
+    canonical(name).ok_or_else(|| anyhow::anyhow!("Unknown language: {}", name))
+}
+
+fn from_path(path: &Path) -> Option<&'static str> {
+    let file_name = path.file_name()?.to_str()?;
+    match file_name {
+        "Dockerfile" | "Containerfile" => return Some("dockerfile"),
+        "Makefile" | "GNUmakefile" | "makefile" => return Some("makefile"),
+        "CMakeLists.txt" => return Some("cmake"),
+        "Gemfile" | "Rakefile" => return Some("ruby"),
+        ".bashrc" | ".bash_profile" | ".profile" | ".zshrc" => return Some("bash"),
+        _ => {}
+    }
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" | "hxx" => "cpp",
+        "cs" => "csharp",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "swift" => "swift",
+        "rb" => "ruby",
+        "php" => "php",
+        "pl" | "pm" => "perl",
+        "lua" => "lua",
+        "sh" | "bash" | "zsh" => "bash",
+        "fish" => "fish",
+        "ps1" => "powershell",
+        "hs" => "haskell",
+        "scala" => "scala",
+        "ex" | "exs" => "elixir",
+        "r" => "r",
+        "sql" => "sql",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "xml" => "xml",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "ini" | "cfg" => "ini",
+        "md" | "markdown" => "markdown",
+        "cmake" => "cmake",
+        "nix" => "nix",
+        "txt" => "text",
+        _ => return None,
+    })
+}
+
+/// `#!/usr/bin/python3`, `#!/usr/bin/env bash`, `#!/usr/bin/env -S node --flag`.
+fn shebang(line: &str) -> Option<&'static str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+    // python3.12 -> python
+    canonical(program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'))
+}
+
+/// `vim: set ft=python:`, `vi: filetype=sh`, `-*- mode: ruby -*-` or `-*- ruby -*-`.
+fn modeline(line: &str) -> Option<&'static str> {
+    if let Some(start) = line.find("-*-") {
+        let rest = &line[start + 3..];
+        let vars = &rest[..rest.find("-*-")?];
+        if !vars.contains(':') {
+            return canonical(vars.trim());
+        }
+        return vars.split(';').find_map(|var| {
+            let (key, value) = var.split_once(':')?;
+            (key.trim().eq_ignore_ascii_case("mode")).then(|| canonical(value.trim())).flatten()
+        });
+    }
+
+    // The marker must start the line or follow whitespace, so `regex:` is no modeline.
+    let idx = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|(idx, _)| line[..*idx].chars().next_back().is_none_or(char::is_whitespace))
+            .map(|(idx, _)| idx + marker.len())
+    })?;
+    line[idx..]
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|option| {
+            let value = option.strip_prefix("ft=").or_else(|| option.strip_prefix("filetype="))?;
+            canonical(value)
+        })
+}
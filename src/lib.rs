@@ -0,0 +1,26 @@
+//! Bundles the files of a directory tree into a single document and back.
+
+pub mod binary;
+pub mod comments;
+pub mod config;
+pub mod eol;
+pub mod filter;
+pub mod git;
+pub mod language;
+pub mod limits;
+pub mod order;
+pub mod output;
+pub mod preamble;
+pub mod references;
+pub mod tokens;
+pub mod tree;
+pub mod unbundle;
+pub mod watch;
+mod bundler;
+mod parallel;
+mod spool;
+mod stream;
+
+pub use bundler::{BundleEntry, BundleSummary, Bundler, FileStats, LanguageStats};
+pub use config::{Config, RootConfig};
+pub use output::OutputFormat;
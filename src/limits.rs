@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    /// Leave over-limit files out of the bundle.
+    #[default]
+    Skip,
+    /// Keep the first lines that fit.
+    Head,
+    /// Keep lines from both ends with an elision marker in between.
+    HeadTail,
+}
+
+/// Size of a file before it was truncated.
+pub struct Truncation {
+    pub original_bytes: u64,
+    pub original_lines: usize,
+}
+
+pub enum Limited {
+    Unchanged(String),
+    Truncated(String, Truncation),
+    Skipped,
+}
+
+#[derive(Clone, Copy)]
+pub struct SizeLimits {
+    pub max_file_bytes: Option<u64>,
+    pub max_file_lines: Option<usize>,
+    pub strategy: TruncationStrategy,
+}
+
+impl SizeLimits {
+    pub fn exceeds_bytes(&self, len: u64) -> bool {
+        self.max_file_bytes.is_some_and(|max| len > max)
+    }
+
+    /// Applies the per-file limits to `text`, whose lines all end in `\n`.
+    pub fn apply(&self, text: String) -> Limited {
+        let original_bytes = text.len() as u64;
+        let original_lines = text.lines().count();
+        let too_many_lines = self.max_file_lines.is_some_and(|max| original_lines > max);
+        if !self.exceeds_bytes(original_bytes) && !too_many_lines {
+            return Limited::Unchanged(text);
+        }
+
+        let max_bytes = self.max_file_bytes.map_or(usize::MAX, |b| b as usize);
+        let max_lines = self.max_file_lines.unwrap_or(usize::MAX);
+        let truncated = match self.strategy {
+            TruncationStrategy::Skip => return Limited::Skipped,
+            TruncationStrategy::Head => {
+                let head = take_lines(text.split_inclusive('\n'), max_lines, max_bytes);
+                if head.is_empty() {
+                    cut_first_line(&text, max_bytes)
+                } else {
+                    head.concat()
+                }
+            }
+            TruncationStrategy::HeadTail => {
+                let head = take_lines(text.split_inclusive('\n'), max_lines / 2, max_bytes / 2);
+                let mut tail = take_lines(
+                    text.split_inclusive('\n').rev(),
+                    max_lines - max_lines / 2,
+                    max_bytes - max_bytes / 2,
+                );
+                tail.reverse();
+                if head.is_empty() && tail.is_empty() {
+                    return Limited::Truncated(
+                        cut_first_line(&text, max_bytes),
+                        Truncation { original_bytes, original_lines },
+                    );
+                }
+                let elided = original_lines - head.len() - tail.len();
+                format!("{}... [{} lines elided] ...\n{}", head.concat(), elided, tail.concat())
+            }
+        };
+        Limited::Truncated(truncated, Truncation { original_bytes, original_lines })
+    }
+}
+
+/// Takes whole lines from `lines` until either limit would be exceeded.
+fn take_lines<'a>(lines: impl Iterator<Item = &'a str>, max_lines: usize, max_bytes: usize) -> Vec<&'a str> {
+    let mut taken = Vec::new();
+    let mut bytes = 0;
+    for line in lines.take(max_lines) {
+        if bytes + line.len() > max_bytes {
+            break;
+        }
+        bytes += line.len();
+        taken.push(line);
+    }
+    taken
+}
+
+/// Cuts an overlong first line (e.g. minified code) so that something of the file remains.
+fn cut_first_line(text: &str, max_bytes: usize) -> String {
+    let line = text.lines().next().unwrap_or_default();
+    format!("{} ... [truncated]\n", &line[..line.floor_char_boundary(max_bytes)])
+}
+
+/// Caps the total number of content bytes written to the bundle.
+pub struct ByteBudget {
+    max_total_bytes: Option<u64>,
+    pub used: u64,
+    pub left_out: Vec<(String, u64)>,
+}
+
+impl ByteBudget {
+    pub fn new(max_total_bytes: Option<u64>) -> Self {
+        ByteBudget { max_total_bytes, used: 0, left_out: Vec::new() }
+    }
+
+    pub fn fits(&self, bytes: u64) -> bool {
+        self.max_total_bytes.is_none_or(|max| self.used + bytes <= max)
+    }
+
+    pub fn add(&mut self, bytes: u64) {
+        self.used += bytes;
+    }
+
+    pub fn leave_out(&mut self, path: &str, bytes: u64) {
+        self.left_out.push((path.to_string(), bytes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_bytes: Option<u64>, max_lines: Option<usize>, strategy: TruncationStrategy) -> SizeLimits {
+        SizeLimits { max_file_bytes: max_bytes, max_file_lines: max_lines, strategy }
+    }
+
+    /// The truncated text, failing unless `text` was truncated.
+    fn truncated(limits: SizeLimits, text: &str) -> String {
+        match limits.apply(text.to_string()) {
+            Limited::Truncated(text, _) => text,
+            _ => panic!("not truncated: {:?}", text),
+        }
+    }
+
+    fn numbered(lines: usize) -> String {
+        (1..=lines).map(|i| format!("{}\n", i)).collect()
+    }
+
+    #[test]
+    fn within_limits_is_unchanged() {
+        let text = numbered(4);
+        let limits = limits(Some(8), Some(4), TruncationStrategy::Head);
+        assert!(matches!(limits.apply(text.clone()), Limited::Unchanged(t) if t == text));
+        assert!(!limits.exceeds_bytes(8));
+        assert!(limits.exceeds_bytes(9));
+    }
+
+    #[test]
+    fn skip_leaves_over_limit_files_out() {
+        let limits = limits(None, Some(3), TruncationStrategy::Skip);
+        assert!(matches!(limits.apply(numbered(4)), Limited::Skipped));
+    }
+
+    #[test]
+    fn head_keeps_whole_lines_within_both_limits() {
+        assert_eq!(truncated(limits(None, Some(2), TruncationStrategy::Head), &numbered(4)), "1\n2\n");
+        assert_eq!(truncated(limits(Some(5), None, TruncationStrategy::Head), &numbered(4)), "1\n2\n");
+        let Limited::Truncated(_, truncation) = limits(Some(5), None, TruncationStrategy::Head).apply(numbered(4))
+        else {
+            panic!("not truncated");
+        };
+        assert_eq!((truncation.original_bytes, truncation.original_lines), (8, 4));
+    }
+
+    #[test]
+    fn head_tail_splits_the_limits_between_both_ends() {
+        let limits_even = limits(None, Some(4), TruncationStrategy::HeadTail);
+        assert_eq!(truncated(limits_even, &numbered(10)), "1\n2\n... [6 lines elided] ...\n9\n10\n");
+        // The tail gets the odd line, and half of the bytes each.
+        let limits_odd = limits(None, Some(5), TruncationStrategy::HeadTail);
+        assert_eq!(truncated(limits_odd, &numbered(10)), "1\n2\n... [5 lines elided] ...\n8\n9\n10\n");
+        let limits_bytes = limits(Some(8), None, TruncationStrategy::HeadTail);
+        assert_eq!(truncated(limits_bytes, &numbered(10)), "1\n2\n... [7 lines elided] ...\n10\n");
+    }
+
+    #[test]
+    fn an_overlong_first_line_is_cut_at_a_char_boundary() {
+        for strategy in [TruncationStrategy::Head, TruncationStrategy::HeadTail] {
+            assert_eq!(truncated(limits(Some(3), None, strategy), "éééé\n"), "é ... [truncated]\n");
+        }
+    }
+
+    #[test]
+    fn byte_budget_counts_what_was_added() {
+        let mut budget = ByteBudget::new(Some(10));
+        assert!(budget.fits(10));
+        budget.add(6);
+        assert!(budget.fits(4) && !budget.fits(5));
+        assert!(ByteBudget::new(None).fits(u64::MAX));
+    }
+}
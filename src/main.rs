@@ -1,104 +1,369 @@
-use std::fs::{self, File};
-use std::io::{self, BufRead, BufReader, Write};
+use std::fs;
+use std::io::{self, BufWriter};
 use std::path::{Path, PathBuf};
-use walkdir::{DirEntry, WalkDir};
-use serde::Deserialize;
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::time::Duration;
 use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use file_bundler::comments::StripComments;
+use file_bundler::config::DEFAULT_CONFIG_TEMPLATE;
+use file_bundler::eol::LineEndings;
+use file_bundler::git::ChangeSet;
+use file_bundler::output::{HeaderField, MarkerStyle};
+use file_bundler::tokens::TokenizerKind;
+use file_bundler::{unbundle, watch, BundleSummary, Bundler, Config, OutputFormat, RootConfig};
 
-#[derive(Deserialize, Default)]
-struct Config {
-    exclude_dirs: Vec<String>,
-    exclude_files: Vec<String>,
-    exclude_patterns: Vec<String>,
+#[derive(Parser)]
+#[command(version, about = "Bundle a directory tree into a single file and back")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 4 {
-        return Err(anyhow::anyhow!("Usage: {} <input_dir> <output_file> <config_path>", args[0]));
-    }
-
-    let input_dir = PathBuf::from(&args[1]);
-    if !input_dir.exists() || !input_dir.is_dir() {
-        return Err(anyhow::anyhow!("Input path must be an existing directory: {}", input_dir.display()));
-    }
-
-    let output_path = PathBuf::from(&args[2]);
+#[derive(Subcommand)]
+enum Command {
+    /// Bundle the files under a directory into one output file
+    Bundle {
+        #[command(flatten)]
+        input: InputArgs,
+        /// File to write the bundle to, or `-` for stdout
+        #[arg(short, long, default_value = "-")]
+        output: PathBuf,
+        /// Keep running and rewrite the output file whenever an input file changes
+        #[arg(long)]
+        watch: bool,
+        /// How long to wait for changes to settle before rewriting, in milliseconds
+        #[arg(long, value_name = "MS", default_value_t = 300, requires = "watch")]
+        debounce_ms: u64,
+    },
+    /// Recreate the files of a text bundle under a target directory
+    Unbundle {
+        bundle: PathBuf,
+        target_dir: PathBuf,
+    },
+    /// Print the files that would be bundled
+    List {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Print per-file and total sizes and token counts without writing a bundle
+    Stats {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Write a commented starter config file
+    Init {
+        #[arg(default_value = ".file_bundler.yaml")]
+        path: PathBuf,
+        /// Overwrite an existing file
+        #[arg(long)]
+        force: bool,
+    },
+}
 
-    let config_path = PathBuf::from(&args[3]);
-    let config: Config = if config_path.exists() {
-        let config_str = fs::read_to_string(&config_path).context("Failed to read config file")?;
-        serde_yaml::from_str(&config_str).context("Failed to parse config file")?
-    } else {
-        println!("No config file found at {}, using defaults.", config_path.display());
-        Config::default()
-    };
+/// Input selection shared by the subcommands that walk a directory.
+#[derive(Args)]
+struct InputArgs {
+    /// Directories to bundle, each optionally labeled as `label=path`; defaults to the
+    /// config's roots, or the current directory
+    #[arg(value_name = "INPUT_DIR")]
+    input_dirs: Vec<String>,
+    /// Config file; when omitted, .file_bundler.yaml or file_bundler.yaml is looked up
+    /// in the first input directory and its parents
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Extra glob pattern to exclude (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+    /// Extra directory to exclude (repeatable)
+    #[arg(long, value_name = "DIR")]
+    exclude_dir: Vec<String>,
+    /// Extra glob pattern to include (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+    /// Extra directory to include (repeatable)
+    #[arg(long, value_name = "DIR")]
+    include_dir: Vec<String>,
+    /// Only bundle files changed in git: worktree (uncommitted), staged, or revisions as
+    /// for `git diff`, e.g. main...HEAD; deleted files are listed as tombstones
+    #[arg(long, value_name = "CHANGES", value_parser = ChangeSet::parse)]
+    changed: Option<ChangeSet>,
+    /// Also write the unified diff of the changes, before the files
+    #[arg(long, requires = "changed")]
+    diff: bool,
+    /// Also bundle unchanged files that the changed files refer to by module name
+    #[arg(long, requires = "changed")]
+    references: bool,
+    /// Bundle the files of this git commit, tag or branch instead of the working tree,
+    /// without checking it out
+    #[arg(long, value_name = "REVISION", conflicts_with = "changed")]
+    at: Option<String>,
+    /// Only bundle files of this language, e.g. rust (repeatable)
+    #[arg(long, value_name = "LANGUAGE")]
+    include_language: Vec<String>,
+    /// Leave out files of this language (repeatable)
+    #[arg(long, value_name = "LANGUAGE")]
+    exclude_language: Vec<String>,
+    /// Output format: text, markdown, json, jsonl or xml
+    #[arg(long, value_parser = OutputFormat::parse)]
+    format: Option<OutputFormat>,
+    /// Line endings: preserve (exact bytes) or lf
+    #[arg(long, value_parser = LineEndings::parse)]
+    line_endings: Option<LineEndings>,
+    /// Remove comments from source files: off, all or keep_docs (keeps doc comments)
+    #[arg(long, value_name = "MODE", value_parser = StripComments::parse)]
+    strip_comments: Option<StripComments>,
+    /// Text format markers: auto, length or boundary
+    #[arg(long, value_parser = MarkerStyle::parse)]
+    marker_style: Option<MarkerStyle>,
+    /// Extra header fields: size, lines, mtime, sha256, language, mode (comma-separated)
+    #[arg(long, value_delimiter = ',', value_parser = HeaderField::parse)]
+    header_fields: Vec<HeaderField>,
+    /// Start the bundle with metadata and a table of contents
+    #[arg(long)]
+    preamble: bool,
+    /// Start the bundle with a directory tree of the included files
+    #[arg(long)]
+    tree: bool,
+    /// Also show excluded files in the tree (implies --tree)
+    #[arg(long)]
+    tree_excluded: bool,
+    /// Collapse directories deeper than this in the tree (implies --tree)
+    #[arg(long, value_name = "DEPTH")]
+    tree_depth: Option<usize>,
+    /// Tokenizer: cl100k, o200k or estimate
+    #[arg(long, value_parser = TokenizerKind::parse)]
+    tokenizer: Option<TokenizerKind>,
+    /// Token budget for the whole bundle
+    #[arg(long)]
+    max_tokens: Option<usize>,
+    /// Number of threads reading files (defaults to the number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Do not honor .gitignore and other ignore files
+    #[arg(long)]
+    no_gitignore: bool,
+}
 
-    let exclude_dir_set: Vec<_> = config.exclude_dirs.iter().map(|s| s.as_str()).collect();
-    let exclude_file_set: Vec<_> = config.exclude_files.iter().map(|s| s.as_str()).collect();
+impl InputArgs {
+    /// Loads the explicit or discovered config and applies the command-line overrides.
+    fn load_config(&self) -> Result<Config> {
+        let config_path = match &self.config {
+            Some(path) => Some(path.clone()),
+            None => Config::discover(&self.roots().first().map_or(PathBuf::from("."), |r| r.path.clone())),
+        };
+        let mut config = match config_path {
+            Some(path) => {
+                eprintln!("Using config file {}", path.display());
+                Config::load(&path)?
+            }
+            None => Config::default(),
+        };
 
-    let mut glob_builder = GlobSetBuilder::new();
-    for pat in &config.exclude_patterns {
-        glob_builder.add(Glob::new(pat).context(format!("Invalid glob pattern: {}", pat))?);
+        config.exclude_patterns.extend(self.exclude.iter().cloned());
+        config.exclude_dirs.extend(self.exclude_dir.iter().cloned());
+        config.include_patterns.extend(self.include.iter().cloned());
+        config.include_dirs.extend(self.include_dir.iter().cloned());
+        config.include_languages.extend(self.include_language.iter().cloned());
+        config.exclude_languages.extend(self.exclude_language.iter().cloned());
+        if let Some(format) = self.format {
+            config.format = format;
+        }
+        if let Some(line_endings) = self.line_endings {
+            config.line_endings = line_endings;
+        }
+        if let Some(strip_comments) = self.strip_comments {
+            config.strip_comments = strip_comments;
+        }
+        if let Some(marker_style) = self.marker_style {
+            config.marker_style = marker_style;
+        }
+        if !self.header_fields.is_empty() {
+            config.header_fields = self.header_fields.clone();
+        }
+        if self.preamble {
+            config.preamble = true;
+        }
+        if self.tree || self.tree_excluded || self.tree_depth.is_some() {
+            config.tree = true;
+        }
+        if self.tree_excluded {
+            config.tree_excluded = true;
+        }
+        if self.tree_depth.is_some() {
+            config.tree_depth = self.tree_depth;
+        }
+        if let Some(tokenizer) = self.tokenizer {
+            config.tokenizer = tokenizer;
+        }
+        if self.max_tokens.is_some() {
+            config.max_tokens = self.max_tokens;
+        }
+        if self.jobs.is_some() {
+            config.jobs = self.jobs;
+        }
+        if self.no_gitignore {
+            config.respect_gitignore = false;
+        }
+        Ok(config)
     }
-    let exclude_pattern_set = glob_builder.build().context("Failed to build globset")?;
 
-    let mut output_file = File::create(&output_path).context("Failed to create output file")?;
+    /// Parses the `label=path` / `path` input arguments.
+    fn roots(&self) -> Vec<RootConfig> {
+        self.input_dirs
+            .iter()
+            .map(|arg| match arg.split_once('=') {
+                Some((label, path)) if !label.is_empty() => RootConfig::new(path).with_label(label),
+                _ => RootConfig::new(arg),
+            })
+            .collect()
+    }
 
-    for entry in WalkDir::new(&input_dir).into_iter().filter_map(|e| e.ok()) {
-        if should_skip(&entry, &input_dir, &exclude_dir_set, &exclude_file_set, &exclude_pattern_set) {
-            continue;
+    fn bundler(&self) -> Result<Bundler> {
+        let config = self.load_config()?;
+        let mut roots = self.roots();
+        if roots.is_empty() && config.roots.is_empty() {
+            roots.push(RootConfig::new("."));
         }
+        let mut bundler = roots.into_iter().fold(Bundler::new().config(config), Bundler::add_root);
+        if let Some(changes) = &self.changed {
+            bundler = bundler.changes(changes.clone()).diff(self.diff).references(self.references);
+        }
+        if let Some(revision) = &self.at {
+            bundler = bundler.revision(revision);
+        }
+        Ok(bundler)
+    }
+}
 
-        if entry.file_type().is_file() {
-            if let Err(e) = process_file(&entry.path(), &input_dir, &mut output_file) {
-                eprintln!("Warning: Failed to process {}: {}", entry.path().display(), e);
+// Bundles and listings go to stdout; status and warnings go to stderr so output can be piped.
+fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Bundle { input, output, watch: true, debounce_ms } => {
+            if output.as_os_str() == "-" {
+                return Err(anyhow::anyhow!("--watch needs an output file (--output)"));
+            }
+            let bundler = input.bundler()?;
+            let config = bundler.config_ref();
+            eprintln!("Watching for changes; press Ctrl-C to stop.");
+            watch::watch(&bundler, &output, Duration::from_millis(debounce_ms), |result| match result {
+                Ok(summary) => {
+                    print_summary(&summary, config);
+                    eprintln!("Bundle written at: {}", output.display());
+                }
+                Err(e) => eprintln!("Error: {:#}", e),
+            })?;
+        }
+        Command::Bundle { input, output, .. } => {
+            let bundler = input.bundler()?;
+            let summary = if output.as_os_str() == "-" {
+                bundler.bundle(BufWriter::new(io::stdout().lock()))?
+            } else {
+                bundler.bundle_to_file(&output)?
+            };
+            print_summary(&summary, bundler.config_ref());
+            if output.as_os_str() != "-" {
+                eprintln!("Bundle created at: {}", output.display());
+            }
+        }
+        Command::Unbundle { bundle, target_dir } => {
+            let report = unbundle::unbundle(&bundle, &target_dir)?;
+            for warning in &report.warnings {
+                eprintln!("Warning: {}", warning);
             }
+            eprintln!("Unbundled {} file(s) into: {}", report.written, target_dir.display());
         }
+        Command::List { input } => {
+            let bundler = input.bundler()?;
+            for entry in bundler.entries()? {
+                match (entry.deleted, entry.referenced) {
+                    (true, _) => println!("{} (deleted)", entry.rel_path.display()),
+                    (false, true) => println!("{} (referenced)", entry.rel_path.display()),
+                    (false, false) => println!("{}", entry.rel_path.display()),
+                }
+            }
+        }
+        Command::Stats { input } => {
+            let bundler = input.bundler()?;
+            let summary = bundler.bundle(io::sink())?;
+            let mut files: Vec<_> = summary.files.iter().collect();
+            files.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.path.cmp(&b.path)));
+            println!("{:>10} {:>8} {:>10}  path", "bytes", "lines", "tokens");
+            for file in files {
+                println!("{:>10} {:>8} {:>10}  {}", file.bytes, file.lines, file.tokens, file.path);
+            }
+            println!(
+                "{:>10} {:>8} {:>10}  total ({} files)",
+                summary.total_bytes,
+                summary.files.iter().map(|f| f.lines).sum::<usize>(),
+                summary.total_tokens,
+                summary.files.len()
+            );
+            println!();
+            println!("{:>10} {:>8} {:>10} {:>6}  language", "bytes", "lines", "tokens", "files");
+            for language in summary.by_language() {
+                println!(
+                    "{:>10} {:>8} {:>10} {:>6}  {}",
+                    language.bytes,
+                    language.lines,
+                    language.tokens,
+                    language.files,
+                    language.language.unwrap_or("(unknown)")
+                );
+            }
+            print_left_out(&summary, bundler.config_ref());
+        }
+        Command::Init { path, force } => init_config(&path, force)?,
     }
-
-    println!("Bundle created at: {}", output_path.display());
     Ok(())
 }
 
-fn should_skip(
-    entry: &DirEntry,
-    root: &Path,
-    exclude_dirs: &[&str],
-    exclude_files: &[&str],
-    exclude_patterns: &GlobSet,
-) -> bool {
-    let rel_path = entry.path().strip_prefix(root).unwrap_or(entry.path());
-    let rel_str = rel_path.to_string_lossy();
-
-    if entry.file_type().is_dir() {
-        exclude_dirs.iter().any(|&dir| rel_str == dir)
-    } else {
-        let is_in_excluded_dir = exclude_dirs.iter().any(|&dir| {
-            let prefix = format!("{}/", dir);
-            rel_str.starts_with(&prefix)
-        });
-        is_in_excluded_dir ||
-        exclude_files.contains(&rel_str.as_ref()) ||
-        exclude_patterns.is_match(rel_path)
+fn print_summary(summary: &BundleSummary, config: &Config) {
+    for (path, reason) in &summary.warnings {
+        eprintln!("Warning: Failed to process {}: {}", path, reason);
+    }
+    if config.token_report {
+        for file in &summary.files {
+            eprintln!("{:>8} tokens  {}", file.tokens, file.path);
+        }
     }
+    eprintln!("Bundled {} file(s), {} tokens.", summary.files.len(), summary.total_tokens);
+    print_left_out(summary, config);
 }
 
-fn process_file(path: &Path, root: &Path, output: &mut File) -> Result<()> {
-    let rel_path = path.strip_prefix(root).unwrap_or(path).display();
-
-    let file = File::open(path).context("Failed to open file")?;
-    let reader = BufReader::new(file);
-    let mut content = String::new();
-    for line in reader.lines() {
-        content.push_str(&line.context("Failed to read line")?);
-        content.push('\n');
+fn print_left_out(summary: &BundleSummary, config: &Config) {
+    if !summary.left_out_size.is_empty() {
+        eprintln!("Per-file size limit exceeded; left out {} file(s):", summary.left_out_size.len());
+        for (path, bytes) in &summary.left_out_size {
+            eprintln!("{:>8} bytes   {}", bytes, path);
+        }
     }
+    if !summary.left_out_bytes.is_empty() {
+        eprintln!(
+            "Size budget of {} bytes exceeded; left out {} file(s):",
+            config.max_total_bytes.unwrap_or_default(),
+            summary.left_out_bytes.len()
+        );
+        for (path, bytes) in &summary.left_out_bytes {
+            eprintln!("{:>8} bytes   {}", bytes, path);
+        }
+    }
+    if !summary.left_out_tokens.is_empty() {
+        eprintln!(
+            "Token budget of {} exceeded; left out {} file(s):",
+            config.max_tokens.unwrap_or_default(),
+            summary.left_out_tokens.len()
+        );
+        for (path, tokens) in &summary.left_out_tokens {
+            eprintln!("{:>8} tokens  {}", tokens, path);
+        }
+    }
+}
 
-    writeln!(output, "--- START FILE: {} ---", rel_path)?;
-    output.write_all(content.as_bytes())?;
-    writeln!(output, "--- END FILE ---\n")?;
-
+fn init_config(path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        return Err(anyhow::anyhow!("{} already exists; pass --force to overwrite it", path.display()));
+    }
+    fs::write(path, DEFAULT_CONFIG_TEMPLATE).context("Failed to write config file")?;
+    eprintln!("Wrote config file: {}", path.display());
     Ok(())
 }
@@ -0,0 +1,77 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Component, Path};
+use std::time::SystemTime;
+use globset::{Glob, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result};
+use crate::bundler::BundleEntry;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOrder {
+    /// Component-wise path order.
+    #[default]
+    Path,
+    /// Path order, but at every level the files of a subdirectory come before the level's own files.
+    DirsFirst,
+    /// Smallest files first.
+    Size,
+    /// Least recently modified first.
+    Mtime,
+}
+
+/// Sorts `entries` by `order`, placing entries that match a `priority` pattern first,
+/// in the order the patterns are listed. Patterns are matched against paths relative to
+/// their root, like exclude rules. Ties fall back to path order.
+pub fn sort_entries(entries: &mut [BundleEntry], order: FileOrder, priority: &[String]) -> Result<()> {
+    let mut matchers = Vec::with_capacity(priority.len());
+    for pat in priority {
+        matchers.push(compile(pat)?);
+    }
+    let rank = |entry: &BundleEntry| {
+        matchers.iter().position(|m| m.is_match(&entry.root_rel_path)).unwrap_or(usize::MAX)
+    };
+
+    match order {
+        FileOrder::Path => entries.sort_by(|a, b| (rank(a), &a.rel_path).cmp(&(rank(b), &b.rel_path))),
+        FileOrder::DirsFirst => entries.sort_by(|a, b| {
+            rank(a).cmp(&rank(b)).then_with(|| dirs_first(&a.rel_path, &b.rel_path))
+        }),
+        FileOrder::Size => entries.sort_by_cached_key(|e| {
+            let size = match &e.blob {
+                Some(blob) => blob.size,
+                None => fs::metadata(&e.path).map_or(0, |m| m.len()),
+            };
+            (rank(e), size, e.rel_path.clone())
+        }),
+        FileOrder::Mtime => entries.sort_by_cached_key(|e| {
+            let mtime = match &e.blob {
+                Some(blob) => blob.mtime(),
+                None => fs::metadata(&e.path).and_then(|m| m.modified()).ok(),
+            };
+            (rank(e), mtime.unwrap_or(SystemTime::UNIX_EPOCH), e.rel_path.clone())
+        }),
+    }
+    Ok(())
+}
+
+fn compile(pattern: &str) -> Result<GlobMatcher> {
+    Ok(Glob::new(pattern)
+        .context(format!("Invalid glob pattern: {}", pattern))?
+        .compile_matcher())
+}
+
+fn dirs_first(a: &Path, b: &Path) -> Ordering {
+    let a: Vec<Component> = a.components().collect();
+    let b: Vec<Component> = b.components().collect();
+    for i in 0..a.len().min(b.len()) {
+        if a[i] == b[i] {
+            continue;
+        }
+        let a_is_dir = i + 1 < a.len();
+        let b_is_dir = i + 1 < b.len();
+        return b_is_dir.cmp(&a_is_dir).then_with(|| a[i].cmp(&b[i]));
+    }
+    a.len().cmp(&b.len())
+}
@@ -0,0 +1,903 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize, Serializer};
+use anyhow::{Context, Result};
+use crate::binary;
+use crate::eol::{EolStyle, TextLayout};
+use crate::limits::Truncation;
+use crate::preamble::Preamble;
+use crate::stream;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// `--- START FILE: <path> ---` / `--- END FILE ---` blocks.
+    #[default]
+    Text,
+    /// A heading per file followed by a language-tagged code fence.
+    Markdown,
+    /// A single JSON document with a `files` array.
+    Json,
+    /// One JSON record per line.
+    Jsonl,
+    /// `<file path="...">` elements inside a `<bundle>` root.
+    Xml,
+}
+
+/// Prefix of the text format's start marker line.
+pub(crate) const START_MARKER: &str = "--- START FILE";
+/// The text format's end marker line.
+pub(crate) const END_MARKER: &str = "--- END FILE ---";
+/// Prefix of the optional first line of a text bundle, which carries bundle-wide attributes.
+pub(crate) const BUNDLE_HEADER: &str = "--- BUNDLE";
+/// Lines around the text format's preamble, whose own lines are YAML and never equal these.
+pub(crate) const PREAMBLE_START: &str = "--- PREAMBLE ---";
+pub(crate) const PREAMBLE_END: &str = "--- END PREAMBLE ---";
+/// Lines around the text format's directory tree, whose own lines start with `.`, `|` or `` ` ``.
+pub(crate) const TREE_START: &str = "--- TREE ---";
+pub(crate) const TREE_END: &str = "--- END TREE ---";
+pub(crate) const DIFF_START: &str = "--- DIFF";
+pub(crate) const DIFF_END: &str = "--- END DIFF ---";
+
+/// How the text format keeps file content from being mistaken for its markers.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MarkerStyle {
+    /// Plain markers; only files containing an end marker line get a `length=N` attribute.
+    #[default]
+    Auto,
+    /// Every file header carries a `length=N` attribute.
+    Length,
+    /// A random boundary, declared in a bundle header, is added to every marker.
+    Boundary,
+}
+
+impl MarkerStyle {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "auto" => Ok(MarkerStyle::Auto),
+            "length" => Ok(MarkerStyle::Length),
+            "boundary" => Ok(MarkerStyle::Boundary),
+            _ => Err(anyhow::anyhow!("Unknown marker style: {} (expected auto, length or boundary)", name)),
+        }
+    }
+}
+
+/// Optional per-file header fields.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderField {
+    /// Size on disk in bytes.
+    Size,
+    /// Number of lines on disk; not set for binary files.
+    Lines,
+    /// Modification time, UTC.
+    Mtime,
+    /// SHA-256 of the bytes on disk.
+    Sha256,
+    /// Language from a vim or emacs modeline, else from the file name, else from a shebang line.
+    Language,
+    /// Unix permission bits, in octal.
+    Mode,
+}
+
+impl HeaderField {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "size" => Ok(HeaderField::Size),
+            "lines" => Ok(HeaderField::Lines),
+            "mtime" => Ok(HeaderField::Mtime),
+            "sha256" => Ok(HeaderField::Sha256),
+            "language" => Ok(HeaderField::Language),
+            "mode" => Ok(HeaderField::Mode),
+            _ => Err(anyhow::anyhow!(
+                "Unknown header field: {} (expected size, lines, mtime, sha256, language or mode)",
+                name
+            )),
+        }
+    }
+}
+
+/// Settings that shape the output beyond the choice of format.
+#[derive(Clone, Copy, Default)]
+pub struct WriterOptions {
+    pub marker_style: MarkerStyle,
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "text" => Ok(OutputFormat::Text),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "xml" => Ok(OutputFormat::Xml),
+            _ => Err(anyhow::anyhow!(
+                "Unknown output format: {} (expected text, markdown, json, jsonl or xml)",
+                name
+            )),
+        }
+    }
+
+    pub fn writer<'a, W: Write + 'a>(self, out: W, options: WriterOptions) -> Box<dyn BundleWriter + 'a> {
+        match self {
+            OutputFormat::Text => Box::new(TextWriter {
+                out,
+                marker_style: options.marker_style,
+                start_marker: START_MARKER.to_string(),
+                end_marker: END_MARKER.to_string(),
+            }),
+            OutputFormat::Markdown => Box::new(MarkdownWriter { out }),
+            OutputFormat::Json => Box::new(JsonWriter { out, first: true }),
+            OutputFormat::Jsonl => Box::new(JsonlWriter { out }),
+            OutputFormat::Xml => Box::new(XmlWriter { out }),
+        }
+    }
+}
+
+pub enum FileContent {
+    Text(String),
+    /// Base64 text, already wrapped into lines.
+    Base64(String),
+    /// The content was left out; only its size and hash are recorded.
+    Omitted { reason: &'static str, size: u64, sha256: String },
+    /// A large text file copied from disk in chunks by the writer instead of being held in memory.
+    /// With `normalize`, line endings are converted to `\n` while copying.
+    Stream { path: PathBuf, size: u64, normalize: bool },
+    /// A file deleted in the selected git changes; only its path is recorded.
+    Deleted,
+}
+
+impl FileContent {
+    /// The text that ends up in the bundle body; empty for omitted, streamed and deleted files.
+    pub fn text(&self) -> &str {
+        match self {
+            FileContent::Text(text) | FileContent::Base64(text) => text,
+            FileContent::Omitted { .. } | FileContent::Stream { .. } | FileContent::Deleted => "",
+        }
+    }
+
+    /// Size of the body in bytes; for streamed files, the size on disk.
+    pub fn byte_len(&self) -> u64 {
+        match self {
+            FileContent::Stream { size, .. } => *size,
+            _ => self.text().len() as u64,
+        }
+    }
+}
+
+pub struct FileRecord {
+    pub path: String,
+    pub content: FileContent,
+    pub truncated: Option<Truncation>,
+    /// Line endings of a text file whose bytes are kept as they are; `None` once normalized to LF.
+    pub layout: Option<TextLayout>,
+    /// Detected by [`language::detect`](crate::language::detect); also the Markdown fence tag.
+    pub language: Option<&'static str>,
+    pub meta: FileMeta,
+}
+
+/// The configured [`HeaderField`]s of a file; fields that were not asked for are `None`.
+#[derive(Serialize, Clone, Default)]
+pub struct FileMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_mode")]
+    pub mode: Option<u32>,
+}
+
+impl FileMeta {
+    /// The set fields as `(key, value)` pairs, in a fixed order.
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = Vec::new();
+        if let Some(size) = self.size {
+            fields.push(("size", size.to_string()));
+        }
+        if let Some(lines) = self.lines {
+            fields.push(("lines", lines.to_string()));
+        }
+        if let Some(mtime) = &self.mtime {
+            fields.push(("mtime", mtime.clone()));
+        }
+        if let Some(sha256) = &self.sha256 {
+            fields.push(("sha256", sha256.clone()));
+        }
+        if let Some(language) = self.language {
+            fields.push(("language", language.to_string()));
+        }
+        if let Some(mode) = self.mode {
+            fields.push(("mode", format_mode(mode)));
+        }
+        fields
+    }
+}
+
+fn format_mode(mode: u32) -> String {
+    format!("{:04o}", mode)
+}
+
+fn serialize_mode<S: Serializer>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_mode(mode.unwrap_or_default()))
+}
+
+impl FileRecord {
+    /// The layout, if it differs from LF with a final newline and therefore needs recording.
+    fn recorded_layout(&self) -> Option<TextLayout> {
+        self.layout.filter(|layout| !layout.is_default())
+    }
+
+    /// Header fields not already carried by the content, like the size and hash of an omitted file.
+    fn meta_fields(&self) -> Vec<(&'static str, String)> {
+        let omitted = matches!(self.content, FileContent::Omitted { .. });
+        let mut fields = self.meta.fields();
+        fields.retain(|(key, _)| !(omitted && matches!(*key, "size" | "sha256")));
+        fields
+    }
+
+    /// `key=value` attributes for the text format's start marker.
+    fn text_attrs(&self) -> Vec<String> {
+        let mut attrs = Vec::new();
+        match &self.content {
+            FileContent::Base64(_) => attrs.push("encoding=base64".to_string()),
+            FileContent::Omitted { reason, size, sha256 } => {
+                attrs.push(format!("omitted={} size={} sha256={}", reason, size, sha256))
+            }
+            FileContent::Deleted => attrs.push("deleted".to_string()),
+            FileContent::Text(_) | FileContent::Stream { .. } => {}
+        }
+        attrs.extend(self.meta_fields().into_iter().map(|(key, value)| format!("{}={}", key, value)));
+        if let Some(t) = &self.truncated {
+            attrs.push(format!("truncated original_bytes={} original_lines={}", t.original_bytes, t.original_lines));
+        }
+        if let Some(layout) = self.recorded_layout() {
+            attrs.push(format!("eol={}", layout.eol));
+            if !layout.final_newline {
+                attrs.push("final_newline=no".to_string());
+            }
+        }
+        attrs
+    }
+}
+
+/// Renders file records into a bundle. Implementations own their output sink.
+pub trait BundleWriter {
+    fn begin(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_file(&mut self, file: &FileRecord) -> Result<()>;
+
+    /// Writes the preamble. Called after the files, once they are all known; the output
+    /// places it between what `begin` and the first `write_file` wrote.
+    fn preamble(&mut self, preamble: &Preamble) -> Result<()>;
+
+    /// Writes the directory tree rendered by [`tree::render`](crate::tree::render), before the files.
+    fn tree(&mut self, tree: &str) -> Result<()>;
+
+    /// Writes the unified diff of the bundled changes, after the tree and before the files.
+    fn diff(&mut self, diff: &str) -> Result<()>;
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+struct TextWriter<W> {
+    out: W,
+    marker_style: MarkerStyle,
+    start_marker: String,
+    end_marker: String,
+}
+
+impl<W: Write> TextWriter<W> {
+    /// The `length=N` value for `file`, if its content needs one to be parsed unambiguously.
+    ///
+    /// In auto mode, streamed files are read an extra time to look for marker collisions.
+    fn content_length(&self, file: &FileRecord) -> Result<Option<u64>> {
+        let check = match self.marker_style {
+            MarkerStyle::Boundary => return Ok(None),
+            MarkerStyle::Length => false,
+            MarkerStyle::Auto => true,
+        };
+        let mut scanner = MarkerScanner::new(&self.end_marker);
+        match &file.content {
+            FileContent::Text(text) | FileContent::Base64(text) => scanner.scan(text),
+            FileContent::Omitted { .. } | FileContent::Deleted => return Ok(None),
+            FileContent::Stream { path, normalize, .. } => stream::for_each_chunk(path, *normalize, |chunk| {
+                scanner.scan(chunk);
+                Ok(())
+            })?,
+        }
+        Ok((!check || scanner.collides()).then_some(scanner.bytes))
+    }
+}
+
+impl<W: Write> BundleWriter for TextWriter<W> {
+    fn begin(&mut self) -> Result<()> {
+        if self.marker_style == MarkerStyle::Boundary {
+            let boundary = random_boundary();
+            writeln!(self.out, "{} [boundary={}] ---\n", BUNDLE_HEADER, boundary)?;
+            self.start_marker = format!("{} {}", START_MARKER, boundary);
+            self.end_marker = format!("--- END FILE {} ---", boundary);
+        }
+        Ok(())
+    }
+
+    fn preamble(&mut self, preamble: &Preamble) -> Result<()> {
+        writeln!(self.out, "{}", PREAMBLE_START)?;
+        serde_yaml::to_writer(&mut self.out, preamble).context("Failed to serialize preamble")?;
+        writeln!(self.out, "{}\n", PREAMBLE_END)?;
+        Ok(())
+    }
+
+    fn tree(&mut self, tree: &str) -> Result<()> {
+        write!(self.out, "{}\n{}{}\n\n", TREE_START, tree, TREE_END)?;
+        Ok(())
+    }
+
+    /// A removed line `-- END DIFF ---` would end the block early, so a diff containing
+    /// one gets a `length=N` attribute, as file content does.
+    fn diff(&mut self, diff: &str) -> Result<()> {
+        let mut scanner = MarkerScanner::new(DIFF_END);
+        scanner.scan(diff);
+        match scanner.collides() {
+            true => writeln!(self.out, "{} [length={}] ---", DIFF_START, diff.len())?,
+            false => writeln!(self.out, "{} ---", DIFF_START)?,
+        }
+        write!(self.out, "{}{}\n\n", diff, DIFF_END)?;
+        Ok(())
+    }
+
+    fn write_file(&mut self, file: &FileRecord) -> Result<()> {
+        let mut attrs = file.text_attrs();
+        if let Some(length) = self.content_length(file)? {
+            attrs.push(format!("length={}", length));
+        }
+        if attrs.is_empty() {
+            writeln!(self.out, "{}: {} ---", self.start_marker, file.path)?;
+        } else {
+            writeln!(self.out, "{} [{}]: {} ---", self.start_marker, attrs.join(" "), file.path)?;
+        }
+        // Content without a final newline gets one here; `final_newline=no` tells unbundle to drop it.
+        let ends_open = match &file.content {
+            FileContent::Text(text) | FileContent::Base64(text) => {
+                self.out.write_all(text.as_bytes())?;
+                !text.is_empty() && !text.ends_with('\n')
+            }
+            FileContent::Omitted { .. } | FileContent::Deleted => false,
+            FileContent::Stream { path, normalize, .. } => {
+                let mut ends_open = false;
+                stream::for_each_chunk(path, *normalize, |chunk| {
+                    ends_open = !chunk.ends_with('\n');
+                    Ok(self.out.write_all(chunk.as_bytes())?)
+                })?;
+                ends_open
+            }
+        };
+        if ends_open {
+            writeln!(self.out)?;
+        }
+        writeln!(self.out, "{}\n", self.end_marker)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush().context("Failed to flush output")
+    }
+}
+
+/// Counts content bytes and looks for lines equal to the end marker, over text that may
+/// arrive in several chunks.
+struct MarkerScanner<'a> {
+    end_marker: &'a str,
+    /// The current line so far, as long as it could still equal the end marker.
+    line: String,
+    line_too_long: bool,
+    collides: bool,
+    bytes: u64,
+}
+
+impl<'a> MarkerScanner<'a> {
+    fn new(end_marker: &'a str) -> Self {
+        MarkerScanner { end_marker, line: String::new(), line_too_long: false, collides: false, bytes: 0 }
+    }
+
+    fn scan(&mut self, text: &str) {
+        self.bytes += text.len() as u64;
+        for part in text.split_inclusive('\n') {
+            let (part, ends_line) = match part.strip_suffix('\n') {
+                Some(part) => (part, true),
+                None => (part, false),
+            };
+            if !self.line_too_long {
+                self.line.push_str(part);
+                self.line_too_long = self.line.len() > self.end_marker.len();
+            }
+            if ends_line {
+                self.end_line();
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.line_too_long && self.line == self.end_marker {
+            self.collides = true;
+        }
+        self.line.clear();
+        self.line_too_long = false;
+    }
+
+    /// Whether some line, including an unterminated last one, equals the end marker.
+    fn collides(&mut self) -> bool {
+        self.end_line();
+        self.collides
+    }
+}
+
+/// A hex string unlikely to occur in any file: a hash of the time, the process id and
+/// the standard library's per-process random hasher keys.
+fn random_boundary() -> String {
+    let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    let seed = RandomState::new().hash_one((nanos, std::process::id()));
+    let mut input = nanos.to_le_bytes().to_vec();
+    input.extend_from_slice(&seed.to_le_bytes());
+    binary::sha256_hex(&input)[..32].to_string()
+}
+
+struct MarkdownWriter<W> {
+    out: W,
+}
+
+impl<W: Write> BundleWriter for MarkdownWriter<W> {
+    fn preamble(&mut self, preamble: &Preamble) -> Result<()> {
+        writeln!(self.out, "# Bundle\n")?;
+        writeln!(self.out, "- Format version: {}", preamble.format_version)?;
+        writeln!(self.out, "- Generator: {}", preamble.generator)?;
+        writeln!(self.out, "- Generated at: {}", preamble.generated_at)?;
+        let roots: Vec<_> = preamble.roots.iter().map(|root| format!("`{}`", root)).collect();
+        writeln!(self.out, "- Roots: {}", roots.join(", "))?;
+        if let Some(revision) = &preamble.revision {
+            writeln!(self.out, "- Revision: `{}`", revision)?;
+        }
+        writeln!(self.out)?;
+
+        let config = serde_yaml::to_string(preamble.config).context("Failed to serialize config")?;
+        writeln!(self.out, "Config:\n\n{}yaml\n{}{}\n", fence_for(&config), config, fence_for(&config))?;
+
+        writeln!(self.out, "| Path | Bytes | Lines | Tokens |")?;
+        writeln!(self.out, "| --- | ---: | ---: | ---: |")?;
+        for file in preamble.files {
+            writeln!(
+                self.out,
+                "| `{}` | {} | {} | {} |",
+                file.path.replace('|', "\\|"),
+                file.bytes,
+                file.lines,
+                file.tokens
+            )?;
+        }
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    fn tree(&mut self, tree: &str) -> Result<()> {
+        let fence = fence_for(tree);
+        writeln!(self.out, "{}text\n{}{}\n", fence, tree, fence)?;
+        Ok(())
+    }
+
+    fn diff(&mut self, diff: &str) -> Result<()> {
+        let fence = fence_for(diff);
+        writeln!(self.out, "{}diff\n{}{}\n", fence, diff, fence)?;
+        Ok(())
+    }
+
+    fn write_file(&mut self, file: &FileRecord) -> Result<()> {
+        writeln!(self.out, "## {}\n", file.path)?;
+        let fields = file.meta_fields();
+        if !fields.is_empty() {
+            let fields: Vec<_> = fields.iter().map(|(key, value)| format!("{}: `{}`", key, value)).collect();
+            writeln!(self.out, "{}\n", fields.join(", "))?;
+        }
+        if let Some(t) = &file.truncated {
+            writeln!(
+                self.out,
+                "_Truncated: original file is {} bytes, {} lines._\n",
+                t.original_bytes, t.original_lines
+            )?;
+        }
+        match &file.content {
+            FileContent::Text(text) => {
+                let fence = fence_for(text);
+                writeln!(self.out, "{}{}", fence, file.language.unwrap_or_default())?;
+                self.out.write_all(text.as_bytes())?;
+                if !text.is_empty() && !text.ends_with('\n') {
+                    writeln!(self.out)?;
+                }
+                writeln!(self.out, "{}\n", fence)?;
+            }
+            FileContent::Base64(encoded) => {
+                writeln!(self.out, "```base64")?;
+                self.out.write_all(encoded.as_bytes())?;
+                writeln!(self.out, "```\n")?;
+            }
+            FileContent::Omitted { reason, size, sha256 } => {
+                writeln!(self.out, "_{} file omitted: {} bytes, sha256 `{}`_\n", reason, size, sha256)?;
+            }
+            FileContent::Deleted => writeln!(self.out, "_file deleted_\n")?,
+            FileContent::Stream { path, normalize, .. } => {
+                // The fence depends on the whole content, so large files are read twice.
+                let mut fence = FenceScanner::default();
+                stream::for_each_chunk(path, *normalize, |chunk| {
+                    fence.scan(chunk);
+                    Ok(())
+                })?;
+                let fence = fence.fence();
+                writeln!(self.out, "{}{}", fence, file.language.unwrap_or_default())?;
+                let mut ends_open = false;
+                stream::for_each_chunk(path, *normalize, |chunk| {
+                    ends_open = !chunk.ends_with('\n');
+                    Ok(self.out.write_all(chunk.as_bytes())?)
+                })?;
+                if ends_open {
+                    writeln!(self.out)?;
+                }
+                writeln!(self.out, "{}\n", fence)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush().context("Failed to flush output")
+    }
+}
+
+/// Picks a backtick fence longer than any backtick run inside `text`.
+fn fence_for(text: &str) -> String {
+    let mut scanner = FenceScanner::default();
+    scanner.scan(text);
+    scanner.fence()
+}
+
+/// Tracks the longest backtick run over text that may arrive in several chunks.
+#[derive(Default)]
+struct FenceScanner {
+    longest: usize,
+    run: usize,
+}
+
+impl FenceScanner {
+    fn scan(&mut self, text: &str) {
+        for c in text.chars() {
+            if c == '`' {
+                self.run += 1;
+                self.longest = self.longest.max(self.run);
+            } else {
+                self.run = 0;
+            }
+        }
+    }
+
+    fn fence(&self) -> String {
+        "`".repeat((self.longest + 1).max(3))
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    omitted: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted: Option<bool>,
+    #[serde(flatten)]
+    meta: FileMeta,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<JsonTruncation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    eol: Option<EolStyle>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    final_newline: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct JsonTruncation {
+    original_bytes: u64,
+    original_lines: usize,
+}
+
+impl<'a> JsonRecord<'a> {
+    fn new(file: &'a FileRecord) -> Self {
+        let mut record = JsonRecord {
+            path: &file.path,
+            content: None,
+            encoding: None,
+            omitted: None,
+            deleted: None,
+            meta: file.meta.clone(),
+            truncated: file.truncated.as_ref().map(|t| JsonTruncation {
+                original_bytes: t.original_bytes,
+                original_lines: t.original_lines,
+            }),
+            eol: file.recorded_layout().map(|layout| layout.eol),
+            final_newline: file.recorded_layout().map(|layout| layout.final_newline),
+        };
+        match &file.content {
+            FileContent::Text(text) => record.content = Some(text),
+            FileContent::Base64(encoded) => {
+                record.content = Some(encoded);
+                record.encoding = Some("base64");
+            }
+            FileContent::Omitted { reason, size, sha256 } => {
+                record.omitted = Some(reason);
+                record.meta.size = Some(*size);
+                record.meta.sha256 = Some(sha256.clone());
+            }
+            FileContent::Deleted => record.deleted = Some(true),
+            // Written chunk by chunk in `write_json_record`.
+            FileContent::Stream { .. } => {}
+        }
+        record
+    }
+}
+
+fn write_json_record<W: Write>(out: &mut W, file: &FileRecord) -> Result<()> {
+    let FileContent::Stream { path, normalize, .. } = &file.content else {
+        return serde_json::to_writer(out, &JsonRecord::new(file)).context("Failed to serialize file record");
+    };
+
+    // Streamed content is escaped chunk by chunk into a single JSON string.
+    write!(out, "{{\"path\":{},\"content\":\"", serde_json::to_string(&file.path)?)?;
+    stream::for_each_chunk(path, *normalize, |chunk| {
+        let escaped = serde_json::to_string(chunk)?;
+        out.write_all(&escaped.as_bytes()[1..escaped.len() - 1])?;
+        Ok(())
+    })?;
+    write!(out, "\"")?;
+    for (key, value) in file.meta_fields() {
+        let value = match key {
+            "size" | "lines" => value,
+            _ => serde_json::to_string(&value)?,
+        };
+        write!(out, ",\"{}\":{}", key, value)?;
+    }
+    if let Some(layout) = file.recorded_layout() {
+        write!(out, ",\"eol\":\"{}\",\"final_newline\":{}", layout.eol, layout.final_newline)?;
+    }
+    write!(out, "}}")?;
+    Ok(())
+}
+
+struct JsonWriter<W> {
+    out: W,
+    first: bool,
+}
+
+impl<W: Write> BundleWriter for JsonWriter<W> {
+    // `"files":[` is written with the first file, or on finish, so the preamble can go before it.
+    fn begin(&mut self) -> Result<()> {
+        write!(self.out, "{{")?;
+        Ok(())
+    }
+
+    fn write_file(&mut self, file: &FileRecord) -> Result<()> {
+        if self.first {
+            write!(self.out, "\"files\":[")?;
+        } else {
+            write!(self.out, ",")?;
+        }
+        self.first = false;
+        writeln!(self.out)?;
+        write_json_record(&mut self.out, file)
+    }
+
+    fn preamble(&mut self, preamble: &Preamble) -> Result<()> {
+        write!(self.out, "\"preamble\":")?;
+        serde_json::to_writer(&mut self.out, preamble).context("Failed to serialize preamble")?;
+        write!(self.out, ",")?;
+        Ok(())
+    }
+
+    fn tree(&mut self, tree: &str) -> Result<()> {
+        write!(self.out, "\"tree\":{},", serde_json::to_string(tree)?)?;
+        Ok(())
+    }
+
+    fn diff(&mut self, diff: &str) -> Result<()> {
+        write!(self.out, "\"diff\":{},", serde_json::to_string(diff)?)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.first {
+            write!(self.out, "\"files\":[")?;
+        }
+        writeln!(self.out, "\n]}}")?;
+        self.out.flush().context("Failed to flush output")
+    }
+}
+
+struct JsonlWriter<W> {
+    out: W,
+}
+
+impl<W: Write> BundleWriter for JsonlWriter<W> {
+    fn write_file(&mut self, file: &FileRecord) -> Result<()> {
+        write_json_record(&mut self.out, file)?;
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    /// A first line of the form `{"preamble":{...}}`.
+    fn preamble(&mut self, preamble: &Preamble) -> Result<()> {
+        write!(self.out, "{{\"preamble\":")?;
+        serde_json::to_writer(&mut self.out, preamble).context("Failed to serialize preamble")?;
+        writeln!(self.out, "}}")?;
+        Ok(())
+    }
+
+    /// A line of the form `{"tree":"..."}`.
+    fn tree(&mut self, tree: &str) -> Result<()> {
+        writeln!(self.out, "{{\"tree\":{}}}", serde_json::to_string(tree)?)?;
+        Ok(())
+    }
+
+    /// A line of the form `{"diff":"..."}`.
+    fn diff(&mut self, diff: &str) -> Result<()> {
+        writeln!(self.out, "{{\"diff\":{}}}", serde_json::to_string(diff)?)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush().context("Failed to flush output")
+    }
+}
+
+struct XmlWriter<W> {
+    out: W,
+}
+
+impl<W: Write> BundleWriter for XmlWriter<W> {
+    fn begin(&mut self) -> Result<()> {
+        writeln!(self.out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(self.out, "<bundle>")?;
+        Ok(())
+    }
+
+    fn preamble(&mut self, preamble: &Preamble) -> Result<()> {
+        writeln!(
+            self.out,
+            "<preamble format_version=\"{}\" generator=\"{}\" generated_at=\"{}\">",
+            preamble.format_version,
+            xml_escape(&preamble.generator),
+            preamble.generated_at
+        )?;
+        for root in &preamble.roots {
+            writeln!(self.out, "<root>{}</root>", xml_escape(root))?;
+        }
+        if let Some(revision) = &preamble.revision {
+            writeln!(self.out, "<revision>{}</revision>", xml_escape(revision))?;
+        }
+        let config = serde_yaml::to_string(preamble.config).context("Failed to serialize config")?;
+        writeln!(self.out, "<config format=\"yaml\">\n{}</config>", xml_escape(&config))?;
+        writeln!(self.out, "<toc>")?;
+        for file in preamble.files {
+            writeln!(
+                self.out,
+                "<entry path=\"{}\" bytes=\"{}\" lines=\"{}\" tokens=\"{}\"/>",
+                xml_escape(&file.path),
+                file.bytes,
+                file.lines,
+                file.tokens
+            )?;
+        }
+        writeln!(self.out, "</toc>")?;
+        writeln!(self.out, "</preamble>")?;
+        Ok(())
+    }
+
+    fn tree(&mut self, tree: &str) -> Result<()> {
+        writeln!(self.out, "<tree>\n{}</tree>", xml_escape(tree))?;
+        Ok(())
+    }
+
+    fn diff(&mut self, diff: &str) -> Result<()> {
+        writeln!(self.out, "<diff>\n{}</diff>", xml_escape(diff))?;
+        Ok(())
+    }
+
+    fn write_file(&mut self, file: &FileRecord) -> Result<()> {
+        let path = xml_escape(&file.path);
+        let meta: String = file
+            .meta_fields()
+            .iter()
+            .map(|(key, value)| format!(" {}=\"{}\"", key, xml_escape(value)))
+            .collect();
+        let layout = match file.recorded_layout() {
+            Some(layout) if layout.final_newline => format!(" eol=\"{}\"", layout.eol),
+            Some(layout) => format!(" eol=\"{}\" final_newline=\"no\"", layout.eol),
+            None => String::new(),
+        };
+        match &file.content {
+            FileContent::Text(text) => {
+                let truncated = match &file.truncated {
+                    Some(t) => format!(
+                        " truncated=\"true\" original_bytes=\"{}\" original_lines=\"{}\"",
+                        t.original_bytes, t.original_lines
+                    ),
+                    None => String::new(),
+                };
+                writeln!(
+                    self.out,
+                    "<file path=\"{}\"{}{}{}>{}</file>",
+                    path,
+                    meta,
+                    truncated,
+                    layout,
+                    xml_escape(text)
+                )?;
+            }
+            FileContent::Base64(encoded) => {
+                writeln!(self.out, "<file path=\"{}\"{} encoding=\"base64\">\n{}</file>", path, meta, encoded)?;
+            }
+            FileContent::Omitted { reason, size, sha256 } => {
+                writeln!(
+                    self.out,
+                    "<file path=\"{}\" omitted=\"{}\" size=\"{}\" sha256=\"{}\"{}/>",
+                    path, reason, size, sha256, meta
+                )?;
+            }
+            FileContent::Deleted => writeln!(self.out, "<file path=\"{}\" deleted=\"true\"{}/>", path, meta)?,
+            FileContent::Stream { path: source, normalize, .. } => {
+                write!(self.out, "<file path=\"{}\"{}{}>", path, meta, layout)?;
+                stream::for_each_chunk(source, *normalize, |chunk| {
+                    Ok(self.out.write_all(xml_escape(chunk).as_bytes())?)
+                })?;
+                writeln!(self.out, "</file>")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        writeln!(self.out, "</bundle>")?;
+        self.out.flush().context("Failed to flush output")
+    }
+}
+
+/// Escapes markup characters and replaces characters XML 1.0 cannot represent.
+///
+/// `\r` is written as a character reference because parsers normalize literal ones to `\n`.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\r' => escaped.push_str("&#13;"),
+            '\t' | '\n' => escaped.push(c),
+            c if (c as u32) < 0x20 || c == '\u{FFFE}' || c == '\u{FFFF}' => escaped.push('\u{FFFD}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use anyhow::Result;
+
+/// Runs `work` over `items` on `jobs` threads and hands the results to `consume`
+/// on the calling thread, in the original order of `items`.
+///
+/// Each item is first weighed: `weigh(item)` returns the units (bytes) it reserves from
+/// a window of `max_in_flight` before it is processed, along with whatever `work` needs
+/// from the weighing. The units are released once the result is consumed, which bounds
+/// the memory held by finished-but-not-yet-consumed results. Reservations are granted
+/// in item order so a later item can never starve the one the consumer is waiting for.
+/// An item heavier than the whole window is let through once nothing else is in flight.
+pub fn ordered_map<T, P, R>(
+    items: &[T],
+    jobs: usize,
+    max_in_flight: u64,
+    weigh: impl Fn(&T) -> (u64, P) + Sync,
+    work: impl Fn(&T, P) -> R + Sync,
+    mut consume: impl FnMut(&T, R) -> Result<()>,
+) -> Result<()>
+where
+    T: Sync,
+    R: Send,
+{
+    let next_item = AtomicUsize::new(0);
+    let window = Window::new(max_in_flight);
+
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            let tx = tx.clone();
+            let (next_item, window, weigh, work) = (&next_item, &window, &weigh, &work);
+            scope.spawn(move || loop {
+                let index = next_item.fetch_add(1, Ordering::SeqCst);
+                let Some(item) = items.get(index) else { break };
+                let (units, weighed) = weigh(item);
+                if !window.acquire(index, units) {
+                    break;
+                }
+                if tx.send((index, units, work(item, weighed))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        let mut result = Ok(());
+        for (index, units, output) in rx {
+            pending.insert(index, (units, output));
+            while let Some((units, output)) = pending.remove(&next) {
+                if result.is_ok() {
+                    result = consume(&items[next], output);
+                    if result.is_err() {
+                        window.abort();
+                    }
+                }
+                window.release(units);
+                next += 1;
+            }
+        }
+        result
+    })
+}
+
+struct Window {
+    max_in_flight: u64,
+    state: Mutex<WindowState>,
+    changed: Condvar,
+}
+
+struct WindowState {
+    next_index: usize,
+    in_flight: u64,
+    aborted: bool,
+}
+
+impl Window {
+    fn new(max_in_flight: u64) -> Self {
+        Window {
+            max_in_flight,
+            state: Mutex::new(WindowState { next_index: 0, in_flight: 0, aborted: false }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Blocks until item `index` may reserve `units`; returns false if the run was aborted.
+    fn acquire(&self, index: usize, units: u64) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if state.aborted {
+                return false;
+            }
+            let fits = state.in_flight == 0 || state.in_flight + units <= self.max_in_flight;
+            if state.next_index == index && fits {
+                state.next_index += 1;
+                state.in_flight += units;
+                self.changed.notify_all();
+                return true;
+            }
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn release(&self, units: u64) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.in_flight -= units;
+        self.changed.notify_all();
+    }
+
+    fn abort(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.aborted = true;
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+    use std::time::Duration;
+
+    #[test]
+    fn results_are_consumed_in_item_order() {
+        let items: Vec<u64> = (0..50).collect();
+        let mut consumed = Vec::new();
+        ordered_map(
+            &items,
+            4,
+            u64::MAX,
+            |&item| (1, item * 2),
+            |&item, doubled| {
+                // Early items finish last.
+                thread::sleep(Duration::from_micros(50 * (50 - item)));
+                doubled
+            },
+            |&item, doubled| {
+                consumed.push((item, doubled));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(consumed, items.iter().map(|&i| (i, i * 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn window_bounds_unconsumed_results_and_is_released() {
+        let items: Vec<u64> = (0..40).collect();
+        let (in_flight, most) = (AtomicU64::new(0), AtomicU64::new(0));
+        ordered_map(
+            &items,
+            8,
+            10,
+            |_| (4, ()),
+            |_, ()| {
+                let now = in_flight.fetch_add(4, Ordering::SeqCst) + 4;
+                most.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_micros(100));
+            },
+            |_, ()| {
+                in_flight.fetch_sub(4, Ordering::SeqCst);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(most.load(Ordering::SeqCst) <= 8);
+    }
+
+    #[test]
+    fn items_heavier_than_the_window_still_run() {
+        let items = [5, 100, 5];
+        let mut consumed = Vec::new();
+        ordered_map(&items, 2, 10, |&w| (w, ()), |&w, ()| w, |_, w| {
+            consumed.push(w);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(consumed, items);
+    }
+
+    #[test]
+    fn consume_errors_stop_the_run() {
+        let items: Vec<u64> = (0..100).collect();
+        let mut consumed = 0;
+        let result = ordered_map(&items, 4, 1, |_| (1, ()), |_, ()| (), |&item, ()| {
+            consumed += 1;
+            if item == 3 { Err(anyhow::anyhow!("stop")) } else { Ok(()) }
+        });
+        assert!(result.is_err());
+        assert_eq!(consumed, 4);
+    }
+}
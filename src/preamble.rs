@@ -0,0 +1,90 @@
+use std::env;
+use std::time::SystemTime;
+use serde::Serialize;
+use anyhow::{Context, Result};
+use crate::bundler::FileStats;
+use crate::config::{Config, RootConfig};
+
+/// Version of the bundle layout, bumped when markers or attributes change incompatibly.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Metadata and table of contents written before the files when `preamble` is enabled.
+#[derive(Serialize)]
+pub struct Preamble<'a> {
+    pub format_version: u32,
+    /// Name and version of the tool that wrote the bundle.
+    pub generator: String,
+    /// UTC time in RFC 3339 form; taken from `SOURCE_DATE_EPOCH` when set.
+    pub generated_at: String,
+    /// Input directories, as `label=path` when labeled.
+    pub roots: Vec<String>,
+    /// The git revision the files were read from, as given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    pub config: &'a Config,
+    /// Every file in the bundle, in bundle order.
+    pub files: &'a [FileStats],
+}
+
+impl<'a> Preamble<'a> {
+    pub fn new(
+        roots: &[RootConfig],
+        revision: Option<&str>,
+        config: &'a Config,
+        files: &'a [FileStats],
+    ) -> Result<Self> {
+        Ok(Preamble {
+            format_version: FORMAT_VERSION,
+            generator: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            generated_at: generated_at()?,
+            roots: roots
+                .iter()
+                .map(|root| match &root.label {
+                    Some(label) => format!("{}={}", label, root.path.display()),
+                    None => root.path.display().to_string(),
+                })
+                .collect(),
+            revision: revision.map(str::to_string),
+            config,
+            files,
+        })
+    }
+}
+
+/// `SOURCE_DATE_EPOCH` for reproducible output, or the current time.
+fn generated_at() -> Result<String> {
+    let secs = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => value
+            .trim()
+            .parse::<i64>()
+            .with_context(|| format!("Invalid SOURCE_DATE_EPOCH: {}", value))?,
+        Err(_) => SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64),
+    };
+    Ok(format_utc(secs))
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DDThh:mm:ssZ`.
+pub(crate) fn format_utc(secs: i64) -> String {
+    let (days, secs_of_day) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60
+    )
+}
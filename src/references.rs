@@ -0,0 +1,39 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+/// File stems that name no module of their own; such files go by their directory's name.
+const GENERIC_STEMS: &[&str] = &["mod", "lib", "main", "index", "__init__", "__main__", "init"];
+/// Shorter module names are too likely to be mentioned by accident.
+const MIN_NAME_LEN: usize = 3;
+
+/// The words mentioned by a set of files, used to find the other files they refer to.
+///
+/// A file counts as referenced when its module name, i.e. its file stem, appears as a
+/// word: `use crate::output`, `from output import`, `require('./output')` and
+/// `#include "output.h"` all refer to `output.rs`, `output.py`, `output.js` or
+/// `output.c`. This errs on the side of including too much.
+#[derive(Default)]
+pub struct References {
+    words: HashSet<String>,
+}
+
+impl References {
+    pub fn add(&mut self, text: &str) {
+        let words = text.split(|c: char| !(c.is_alphanumeric() || c == '_'));
+        self.words.extend(words.filter(|word| word.len() >= MIN_NAME_LEN).map(str::to_string));
+    }
+
+    /// Whether the file at `path` is referenced.
+    pub fn mentions(&self, path: &Path) -> bool {
+        module_name(path).is_some_and(|name| self.words.contains(name))
+    }
+}
+
+/// `src/output.rs` -> `output`, `src/output/mod.rs` -> `output`, `pkg/__init__.py` -> `pkg`.
+fn module_name(path: &Path) -> Option<&str> {
+    let stem = path.file_stem()?.to_str()?;
+    if !GENERIC_STEMS.contains(&stem) {
+        return Some(stem);
+    }
+    path.parent()?.file_name()?.to_str()
+}
@@ -0,0 +1,106 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::rc::Rc;
+use anyhow::{Context, Result};
+
+/// Part of the output a write currently goes to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    /// The format's opening, e.g. `<bundle>`.
+    Head,
+    /// The file records.
+    Body,
+    /// Written after the body but placed right after the head.
+    Preamble,
+    /// The format's closing, e.g. `</bundle>`.
+    Tail,
+}
+
+/// Output that lets a preamble, which can only be rendered once every file is known,
+/// end up before the files.
+///
+/// The body is spooled to a temporary file so that it is never held in memory; the
+/// other sections are small and kept in memory. Without a preamble, everything is
+/// written straight through. Cloned handles share the same spool.
+pub struct Spool<W> {
+    inner: Rc<RefCell<SpoolState<W>>>,
+}
+
+struct SpoolState<W> {
+    out: W,
+    section: Section,
+    /// `None` when writing straight through.
+    body: Option<BufWriter<File>>,
+    head: Vec<u8>,
+    preamble: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+impl<W: Write> Spool<W> {
+    pub fn new(out: W, reorder: bool) -> Result<Self> {
+        let body = match reorder {
+            true => Some(BufWriter::new(tempfile::tempfile().context("Failed to create temporary file")?)),
+            false => None,
+        };
+        let state = SpoolState {
+            out,
+            section: Section::Head,
+            body,
+            head: Vec::new(),
+            preamble: Vec::new(),
+            tail: Vec::new(),
+        };
+        Ok(Spool { inner: Rc::new(RefCell::new(state)) })
+    }
+
+    pub fn handle(&self) -> Self {
+        Spool { inner: Rc::clone(&self.inner) }
+    }
+
+    pub fn section(&self, section: Section) {
+        self.inner.borrow_mut().section = section;
+    }
+
+    /// Writes the sections to the output in order: head, preamble, body, tail.
+    pub fn finish(&self) -> Result<()> {
+        let mut state = self.inner.borrow_mut();
+        let state = &mut *state;
+        let Some(body) = state.body.take() else {
+            return state.out.flush().context("Failed to flush output");
+        };
+        let mut body = body.into_inner().map_err(|e| e.into_error()).context("Failed to write temporary file")?;
+        body.rewind().context("Failed to read temporary file")?;
+        state.out.write_all(&state.head)?;
+        state.out.write_all(&state.preamble)?;
+        io::copy(&mut body, &mut state.out).context("Failed to copy temporary file to the output")?;
+        state.out.write_all(&state.tail)?;
+        state.out.flush().context("Failed to flush output")
+    }
+}
+
+impl<W: Write> Write for Spool<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.inner.borrow_mut();
+        let state = &mut *state;
+        let Some(body) = &mut state.body else {
+            return state.out.write(buf);
+        };
+        match state.section {
+            Section::Head => state.head.extend_from_slice(buf),
+            Section::Body => return body.write(buf),
+            Section::Preamble => state.preamble.extend_from_slice(buf),
+            Section::Tail => state.tail.extend_from_slice(buf),
+        }
+        Ok(buf.len())
+    }
+
+    /// Only flushes when writing straight through; a spooled output is flushed by [`Spool::finish`].
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self.inner.borrow_mut();
+        match state.body {
+            Some(_) => Ok(()),
+            None => state.out.flush(),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use anyhow::{Context, Result};
+
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Reads the text file at `path` in fixed-size chunks and passes each decoded chunk to `f`,
+/// so arbitrarily large files never have to be held in memory.
+///
+/// Invalid UTF-8 becomes U+FFFD. With `normalize`, the chunks add up to the same text the
+/// in-memory LF path produces: `\r\n` becomes `\n` and a missing final newline is added;
+/// otherwise line endings are passed through untouched. Multi-byte characters and
+/// `\r\n` pairs split across chunk boundaries are carried over.
+pub fn for_each_chunk(path: &Path, normalize: bool, mut f: impl FnMut(&str) -> Result<()>) -> Result<()> {
+    let mut file = File::open(path).context("Failed to open file")?;
+    let mut buf = vec![0; CHUNK_LEN];
+    let mut carry = Vec::new();
+    let mut text = String::with_capacity(CHUNK_LEN + 4);
+    let mut pending_cr = false;
+    let mut last_char = None;
+
+    loop {
+        let n = file.read(&mut buf).context("Failed to read file")?;
+        let at_eof = n == 0;
+        carry.extend_from_slice(&buf[..n]);
+
+        text.clear();
+        let consumed = decode_lossy(&carry, at_eof, &mut text);
+        carry.drain(..consumed);
+
+        if !normalize {
+            if !text.is_empty() {
+                f(&text)?;
+            }
+            if at_eof {
+                return Ok(());
+            }
+            continue;
+        }
+
+        let mut normalized = String::with_capacity(text.len() + 1);
+        for c in text.chars() {
+            if pending_cr {
+                pending_cr = false;
+                if c != '\n' {
+                    normalized.push('\r');
+                }
+            }
+            if c == '\r' {
+                pending_cr = true;
+            } else {
+                normalized.push(c);
+            }
+        }
+        if at_eof && pending_cr {
+            normalized.push('\r');
+        }
+        if let Some(c) = normalized.chars().next_back() {
+            last_char = Some(c);
+        }
+        if at_eof && last_char.is_some_and(|c| c != '\n') {
+            normalized.push('\n');
+        }
+        if !normalized.is_empty() {
+            f(&normalized)?;
+        }
+        if at_eof {
+            return Ok(());
+        }
+    }
+}
+
+/// Appends the decodable prefix of `bytes` to `out`, replacing invalid sequences, and
+/// returns how many bytes were consumed. An incomplete sequence at the end is left
+/// for the next chunk unless `at_eof`.
+fn decode_lossy(bytes: &[u8], at_eof: bool, out: &mut String) -> usize {
+    let mut pos = 0;
+    loop {
+        match std::str::from_utf8(&bytes[pos..]) {
+            Ok(valid) => {
+                out.push_str(valid);
+                return bytes.len();
+            }
+            Err(e) => {
+                let valid_end = pos + e.valid_up_to();
+                // The prefix up to `valid_up_to` is valid UTF-8 by definition.
+                out.push_str(std::str::from_utf8(&bytes[pos..valid_end]).unwrap_or_default());
+                match e.error_len() {
+                    Some(len) => {
+                        out.push('\u{FFFD}');
+                        pos = valid_end + len;
+                    }
+                    None if at_eof => {
+                        out.push('\u{FFFD}');
+                        return bytes.len();
+                    }
+                    None => return valid_end,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::eol;
+
+    /// Streams `bytes` from a file and returns the chunks joined.
+    fn streamed(bytes: &[u8], normalize: bool) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, bytes).unwrap();
+        let mut text = String::new();
+        for_each_chunk(&path, normalize, |chunk| {
+            text.push_str(chunk);
+            Ok(())
+        })
+        .unwrap();
+        text
+    }
+
+    /// `len` bytes of `x` lines, followed by `tail`.
+    fn padded(len: usize, tail: &str) -> String {
+        let mut text = "x".repeat(len);
+        for i in (0..len).step_by(80).skip(1) {
+            text.replace_range(i - 1..i, "\n");
+        }
+        text + tail
+    }
+
+    #[test]
+    fn crlf_split_across_chunks() {
+        let text = padded(CHUNK_LEN - 1, "\r\nlast\r\n");
+        assert_eq!(streamed(text.as_bytes(), true), text.replace("\r\n", "\n"));
+        assert_eq!(streamed(text.as_bytes(), false), text);
+    }
+
+    #[test]
+    fn multi_byte_characters_split_across_chunks() {
+        for offset in 1..4 {
+            let text = padded(CHUNK_LEN - offset, "€é\n");
+            assert_eq!(streamed(text.as_bytes(), true), text);
+            assert_eq!(streamed(text.as_bytes(), false), text);
+        }
+    }
+
+    #[test]
+    fn normalizing_matches_the_in_memory_path() {
+        for text in ["a\r\nb", "a\nb\n", "a\r", "a\rb\r\n", "\r\n", ""] {
+            let expected = if text.is_empty() { String::new() } else { eol::normalize_lf(text) };
+            assert_eq!(streamed(text.as_bytes(), true), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn missing_final_newline_is_added_only_when_normalizing() {
+        let text = padded(CHUNK_LEN, "end");
+        assert_eq!(streamed(text.as_bytes(), true), text.clone() + "\n");
+        assert_eq!(streamed(text.as_bytes(), false), text);
+        assert_eq!(streamed(b"", true), "");
+    }
+
+    #[test]
+    fn invalid_utf8_becomes_replacement_characters() {
+        assert_eq!(streamed(b"caf\xE9\n", false), "caf\u{FFFD}\n");
+        // A character cut off by the end of the file.
+        assert_eq!(streamed(b"end\xE2\x82", true), "end\u{FFFD}\n");
+    }
+}
@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::CoreBPE;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenizerKind {
+    /// The cl100k_base BPE vocabulary (bundled with the binary, no network access).
+    #[default]
+    Cl100k,
+    /// The o200k_base BPE vocabulary.
+    O200k,
+    /// Roughly one token per four characters; much faster than BPE.
+    Estimate,
+}
+
+impl TokenizerKind {
+    pub fn parse(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "cl100k" => Ok(TokenizerKind::Cl100k),
+            "o200k" => Ok(TokenizerKind::O200k),
+            "estimate" => Ok(TokenizerKind::Estimate),
+            _ => Err(anyhow::anyhow!("Unknown tokenizer: {} (expected cl100k, o200k or estimate)", name)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetPolicy {
+    /// Stop adding files once the first one does not fit.
+    #[default]
+    Stop,
+    /// Leave out files that do not fit but keep trying later, smaller ones.
+    Drop,
+}
+
+pub enum Tokenizer {
+    Bpe(&'static CoreBPE),
+    Estimate,
+}
+
+impl Tokenizer {
+    pub fn new(kind: TokenizerKind) -> Self {
+        match kind {
+            TokenizerKind::Cl100k => Tokenizer::Bpe(tiktoken_rs::cl100k_base_singleton()),
+            TokenizerKind::O200k => Tokenizer::Bpe(tiktoken_rs::o200k_base_singleton()),
+            TokenizerKind::Estimate => Tokenizer::Estimate,
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        match self {
+            Tokenizer::Bpe(bpe) => bpe.encode_ordinary(text).len(),
+            Tokenizer::Estimate => estimate(text),
+        }
+    }
+}
+
+/// The chars/4 heuristic, rounded up.
+pub fn estimate(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Tracks token usage across the bundle and decides which files still fit.
+pub struct TokenBudget {
+    max_tokens: Option<usize>,
+    policy: BudgetPolicy,
+    exhausted: bool,
+    pub used: usize,
+    pub left_out: Vec<(String, usize)>,
+}
+
+impl TokenBudget {
+    pub fn new(max_tokens: Option<usize>, policy: BudgetPolicy) -> Self {
+        TokenBudget { max_tokens, policy, exhausted: false, used: 0, left_out: Vec::new() }
+    }
+
+    /// Returns whether a file of `tokens` tokens may be added, recording it either way.
+    pub fn admit(&mut self, path: &str, tokens: usize) -> bool {
+        let fits = match self.max_tokens {
+            Some(max) => !self.exhausted && self.used + tokens <= max,
+            None => true,
+        };
+        if fits {
+            self.used += tokens;
+        } else {
+            if self.policy == BudgetPolicy::Stop {
+                self.exhausted = true;
+            }
+            self.left_out.push((path.to_string(), tokens));
+        }
+        fits
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A directory in the tree; files are leaves without children.
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<String, Node>,
+    is_file: bool,
+    included: bool,
+    /// Included files at or below this node.
+    included_files: usize,
+    /// Excluded files at or below this node.
+    excluded_files: usize,
+}
+
+impl Node {
+    fn insert(&mut self, path: &Path, included: bool) {
+        let mut node = self;
+        for component in path.components() {
+            node.count(included);
+            node = node.children.entry(component.as_os_str().to_string_lossy().into_owned()).or_default();
+        }
+        node.count(included);
+        node.is_file = true;
+        node.included = included;
+    }
+
+    fn count(&mut self, included: bool) {
+        if included {
+            self.included_files += 1;
+        } else {
+            self.excluded_files += 1;
+        }
+    }
+}
+
+/// Renders bundle paths as an ASCII tree in the style of `tree --charset=ascii`.
+///
+/// Each path comes with whether it is included in the bundle. Excluded files are only
+/// shown, marked, when `show_excluded` is set; a directory without any included file is
+/// then shown collapsed. Directories deeper than `max_depth` are collapsed into a count.
+pub fn render<'a>(
+    paths: impl IntoIterator<Item = (&'a Path, bool)>,
+    show_excluded: bool,
+    max_depth: Option<usize>,
+) -> String {
+    let mut root = Node::default();
+    for (path, included) in paths {
+        if included || show_excluded {
+            root.insert(path, included);
+        }
+    }
+    let mut out = String::from(".\n");
+    render_children(&root, "", 1, max_depth, &mut out);
+    out
+}
+
+fn render_children(node: &Node, prefix: &str, depth: usize, max_depth: Option<usize>, out: &mut String) {
+    let count = node.children.len();
+    for (idx, (name, child)) in node.children.iter().enumerate() {
+        let last = idx + 1 == count;
+        out.push_str(prefix);
+        out.push_str(if last { "`-- " } else { "|-- " });
+        out.push_str(name);
+        if child.is_file {
+            if !child.included {
+                out.push_str(" [excluded]");
+            }
+            out.push('\n');
+            continue;
+        }
+
+        out.push('/');
+        if child.included_files == 0 {
+            out.push_str(" [excluded]\n");
+        } else if max_depth.is_some_and(|max| depth >= max) {
+            let files = if child.included_files == 1 { "file" } else { "files" };
+            match child.excluded_files {
+                0 => out.push_str(&format!(" ({} {})\n", child.included_files, files)),
+                excluded => out.push_str(&format!(" ({} {}, {} excluded)\n", child.included_files, files, excluded)),
+            }
+        } else {
+            out.push('\n');
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "|   " });
+            render_children(child, &prefix, depth + 1, max_depth, out);
+        }
+    }
+}
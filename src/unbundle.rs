@@ -0,0 +1,410 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use anyhow::{Context, Result};
+use crate::binary;
+use crate::output::{
+    BUNDLE_HEADER, DIFF_END, DIFF_START, END_MARKER, PREAMBLE_END, PREAMBLE_START, START_MARKER, TREE_END, TREE_START,
+};
+
+const MARKER_END: &str = " ---";
+
+pub struct BundledFile {
+    pub path: String,
+    /// `key=value` attributes from the start marker, e.g. `encoding=base64`.
+    pub attrs: Vec<(String, String)>,
+    pub content: String,
+}
+
+impl BundledFile {
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub(crate) fn decoded_content(&self) -> Result<Vec<u8>> {
+        match self.attr("encoding") {
+            None => Ok(self.content.clone().into_bytes()),
+            Some("base64") => binary::decode_base64(&self.content),
+            Some(other) => Err(anyhow::anyhow!("Unsupported encoding for {}: {}", self.path, other)),
+        }
+    }
+}
+
+/// Outcome of [`unbundle`].
+pub struct UnbundleReport {
+    pub written: usize,
+    pub warnings: Vec<String>,
+}
+
+/// Recreates every file in the bundle at `bundle_path` under `target_dir`.
+///
+/// Nothing is written if any target file already exists. Files are only ever created, never
+/// overwritten, and never through a symlink or in a directory outside `target_dir`.
+pub fn unbundle(bundle_path: &Path, target_dir: &Path) -> Result<UnbundleReport> {
+    let bundle = fs::read_to_string(bundle_path).context("Failed to read bundle file")?;
+    let mut files = parse_bundle(&bundle)?;
+    let mut warnings = Vec::new();
+    files.retain(|file| match file.attr("omitted") {
+        Some(reason) => {
+            warnings.push(format!("Skipping {} ({} content was not embedded)", file.path, reason));
+            false
+        }
+        None if file.attr("deleted").is_some() => {
+            warnings.push(format!("Skipping {} (deleted in the bundled changes)", file.path));
+            false
+        }
+        None => true,
+    });
+    for file in &files {
+        if file.attr("truncated").is_some() {
+            warnings.push(format!("{} was truncated in the bundle; restoring the truncated content", file.path));
+        }
+    }
+
+    let mut targets = Vec::with_capacity(files.len());
+    let mut seen = HashSet::new();
+    for file in &files {
+        let rel_path = safe_relative_path(&file.path)?;
+        if !seen.insert(rel_path.clone()) {
+            return Err(anyhow::anyhow!("Duplicate file in bundle: {}", file.path));
+        }
+        targets.push(rel_path);
+    }
+
+    // Dangling symlinks count as conflicts too.
+    let conflicts: Vec<_> =
+        targets.iter().map(|p| target_dir.join(p)).filter(|p| p.symlink_metadata().is_ok()).collect();
+    if !conflicts.is_empty() {
+        let listing: Vec<_> = conflicts.iter().map(|p| format!("  {}", p.display())).collect();
+        return Err(anyhow::anyhow!(
+            "Refusing to overwrite {} existing file(s) in {}:\n{}",
+            conflicts.len(),
+            target_dir.display(),
+            listing.join("\n")
+        ));
+    }
+
+    fs::create_dir_all(target_dir).context(format!("Failed to create directory {}", target_dir.display()))?;
+    let root = target_dir.canonicalize().context(format!("Failed to resolve {}", target_dir.display()))?;
+    for (file, rel_path) in files.iter().zip(&targets) {
+        let parent = create_parent_dirs(&root, rel_path)?;
+        let target = parent.join(rel_path.file_name().context("Empty file path in bundle")?);
+        let mut out = match OpenOptions::new().write(true).create_new(true).open(&target) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(anyhow::anyhow!("Refusing to overwrite {}", target.display()));
+            }
+            result => result.context(format!("Failed to create {}", target.display()))?,
+        };
+        out.write_all(&file.decoded_content()?).context(format!("Failed to write {}", target.display()))?;
+    }
+
+    Ok(UnbundleReport { written: files.len(), warnings })
+}
+
+/// Splits a text bundle into its files.
+///
+/// A `length=N` attribute gives the exact content size, so such content may contain
+/// anything, including marker lines. A bundle header declaring a `boundary` changes the
+/// markers for the whole bundle.
+pub fn parse_bundle(bundle: &str) -> Result<Vec<BundledFile>> {
+    let mut files = Vec::new();
+    let mut start_marker = START_MARKER.to_string();
+    let mut end_marker = END_MARKER.to_string();
+    let mut pos = 0;
+
+    while pos < bundle.len() {
+        let (line, next) = next_line(bundle, pos);
+        if line.is_empty() {
+            pos = next;
+            continue;
+        }
+        if pos == 0 {
+            if let Some(attrs) = parse_bundle_header(line) {
+                if let Some((_, boundary)) = attrs.iter().find(|(k, _)| k == "boundary") {
+                    start_marker = format!("{} {}", START_MARKER, boundary);
+                    end_marker = format!("--- END FILE {} ---", boundary);
+                }
+                pos = next;
+                continue;
+            }
+        }
+        if files.is_empty() && line == PREAMBLE_START {
+            pos = skip_block(bundle, next, PREAMBLE_END)?;
+            continue;
+        }
+        if files.is_empty() && line == TREE_START {
+            pos = skip_block(bundle, next, TREE_END)?;
+            continue;
+        }
+        if let Some(attrs) = parse_diff_start(line).filter(|_| files.is_empty()) {
+            pos = match attrs.iter().find(|(k, _)| k == "length") {
+                Some((_, length)) => {
+                    let length: usize =
+                        length.parse().map_err(|_| anyhow::anyhow!("Invalid diff length: {}", length))?;
+                    let end = next.checked_add(length).filter(|&end| bundle.is_char_boundary(end));
+                    match end.map(|end| next_line(bundle, end)) {
+                        Some((DIFF_END, after)) => after,
+                        _ => return Err(anyhow::anyhow!("Missing {} line after the diff", DIFF_END)),
+                    }
+                }
+                None => skip_block(bundle, next, DIFF_END)?,
+            };
+            continue;
+        }
+        let Some((attrs, path)) = parse_start_marker(line, &start_marker) else {
+            return Err(anyhow::anyhow!("Unexpected content outside of a file block at line {}", line_no(bundle, pos)));
+        };
+        let mut file = BundledFile { path: path.to_string(), attrs, content: String::new() };
+        pos = next;
+
+        match file.attr("length") {
+            Some(length) => {
+                let length: usize = length
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid length for {}: {}", file.path, length))?;
+                let end = pos
+                    .checked_add(length)
+                    .filter(|&end| bundle.is_char_boundary(end))
+                    .ok_or_else(|| anyhow::anyhow!("Content of {} is shorter than its declared length", file.path))?;
+                file.content = bundle[pos..end].to_string();
+                pos = end;
+                // The writer terminated the last line itself when the content had no final newline.
+                if !file.content.is_empty() && !file.content.ends_with('\n') && bundle[pos..].starts_with('\n') {
+                    pos += 1;
+                }
+                let (line, next) = next_line(bundle, pos);
+                if line != end_marker {
+                    return Err(anyhow::anyhow!("Missing end marker after the content of {}", file.path));
+                }
+                pos = next;
+            }
+            None => loop {
+                if pos >= bundle.len() {
+                    return Err(anyhow::anyhow!("Missing end marker for {}", file.path));
+                }
+                // Lines keep their terminator so that `\r` and other content bytes survive unchanged.
+                let (line, next) = next_line(bundle, pos);
+                if line == end_marker {
+                    // The writer terminated the last line itself when the file had no final newline.
+                    if file.attr("final_newline") == Some("no") {
+                        file.content.pop();
+                    }
+                    pos = next;
+                    break;
+                }
+                file.content.push_str(&bundle[pos..next]);
+                pos = next;
+            },
+        }
+        files.push(file);
+    }
+
+    Ok(files)
+}
+
+/// Returns the line starting at `pos` without its `\n`, and the position after it.
+fn next_line(bundle: &str, pos: usize) -> (&str, usize) {
+    let rest = &bundle[pos..];
+    match rest.find('\n') {
+        Some(idx) => (&rest[..idx], pos + idx + 1),
+        None => (rest, bundle.len()),
+    }
+}
+
+/// Returns the position after the `end` line of a block whose first line starts at `pos`.
+fn skip_block(bundle: &str, mut pos: usize, end: &str) -> Result<usize> {
+    while pos < bundle.len() {
+        let (line, next) = next_line(bundle, pos);
+        if line == end {
+            return Ok(next);
+        }
+        pos = next;
+    }
+    Err(anyhow::anyhow!("Missing {} line", end))
+}
+
+fn line_no(bundle: &str, pos: usize) -> usize {
+    bundle[..pos].matches('\n').count() + 1
+}
+
+/// Parses `--- BUNDLE [k=v ...] ---`.
+fn parse_bundle_header(line: &str) -> Option<Vec<(String, String)>> {
+    let attrs = line.strip_prefix(BUNDLE_HEADER)?.strip_suffix(MARKER_END)?;
+    Some(parse_attrs(attrs.strip_prefix(" [")?.strip_suffix(']')?))
+}
+
+/// Parses `--- DIFF ---` or `--- DIFF [k=v ...] ---`.
+fn parse_diff_start(line: &str) -> Option<Vec<(String, String)>> {
+    let rest = line.strip_prefix(DIFF_START)?.strip_suffix(MARKER_END)?;
+    if rest.is_empty() {
+        return Some(Vec::new());
+    }
+    Some(parse_attrs(rest.strip_prefix(" [")?.strip_suffix(']')?))
+}
+
+/// Parses `<start>: <path> ---` or `<start> [k=v ...]: <path> ---`, where `<start>` is
+/// `--- START FILE`, followed by the boundary if the bundle declares one.
+fn parse_start_marker<'a>(line: &'a str, start_marker: &str) -> Option<(Vec<(String, String)>, &'a str)> {
+    let rest = line.strip_prefix(start_marker)?.strip_suffix(MARKER_END)?;
+    if let Some(path) = rest.strip_prefix(": ") {
+        return Some((Vec::new(), path));
+    }
+
+    let (attrs, path) = rest.strip_prefix(" [")?.split_once("]: ")?;
+    Some((parse_attrs(attrs), path))
+}
+
+fn parse_attrs(attrs: &str) -> Vec<(String, String)> {
+    attrs
+        .split_whitespace()
+        .map(|attr| match attr.split_once('=') {
+            Some((k, v)) => (k.to_string(), v.to_string()),
+            None => (attr.to_string(), String::new()),
+        })
+        .collect()
+}
+
+/// Creates the directories of `rel_path` below `root` one at a time and returns the
+/// canonical parent directory; symlinks may not lead out of `root`.
+fn create_parent_dirs(root: &Path, rel_path: &Path) -> Result<PathBuf> {
+    let mut dir = root.to_path_buf();
+    for part in rel_path.parent().into_iter().flat_map(Path::components) {
+        let next = dir.join(part);
+        if next.symlink_metadata().is_err() {
+            fs::create_dir(&next).context(format!("Failed to create directory {}", next.display()))?;
+        }
+        dir = next.canonicalize().context(format!("Failed to resolve {}", next.display()))?;
+        if !dir.starts_with(root) || !dir.is_dir() {
+            return Err(anyhow::anyhow!("Refusing to write outside the target directory: {}", next.display()));
+        }
+    }
+    Ok(dir)
+}
+
+fn safe_relative_path(path: &str) -> Result<PathBuf> {
+    let mut rel_path = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => rel_path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(anyhow::anyhow!("Refusing path that escapes the target directory: {}", path));
+            }
+        }
+    }
+    if rel_path.as_os_str().is_empty() {
+        return Err(anyhow::anyhow!("Empty file path in bundle"));
+    }
+    Ok(rel_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::eol::TextLayout;
+    use crate::output::{FileContent, FileMeta, FileRecord, MarkerStyle, OutputFormat, WriterOptions};
+    use crate::preamble::Preamble;
+
+    fn text_file(path: &str, content: &str) -> FileRecord {
+        FileRecord {
+            path: path.to_string(),
+            content: FileContent::Text(content.to_string()),
+            truncated: None,
+            layout: Some(TextLayout::detect(content.as_bytes())),
+            language: None,
+            meta: FileMeta::default(),
+        }
+    }
+
+    /// Writes `files` as a text bundle, with a preamble, tree and diff when given.
+    fn write(marker_style: MarkerStyle, files: &[FileRecord], extras: Option<&str>) -> String {
+        let mut out = Vec::new();
+        {
+            let mut writer = OutputFormat::Text.writer(&mut out, WriterOptions { marker_style });
+            writer.begin().unwrap();
+            if let Some(extra) = extras {
+                let config = Config::default();
+                writer.preamble(&Preamble::new(&[], None, &config, &[]).unwrap()).unwrap();
+                writer.tree(&format!(".\n└── {}\n", extra)).unwrap();
+                // A removed line `-- END DIFF ---` collides with the end of the diff block.
+                writer.diff(&format!("--- a/{0}\n+++ b/{0}\n--- END DIFF ---\n--- END FILE ---\n", extra)).unwrap();
+            }
+            for file in files {
+                writer.write_file(file).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Parses the bundle and returns each file's path and decoded content.
+    fn round_trip(bundle: &str) -> Vec<(String, Vec<u8>)> {
+        let files = parse_bundle(bundle).unwrap();
+        files.iter().map(|file| (file.path.clone(), file.decoded_content().unwrap())).collect()
+    }
+
+    fn expected(files: &[(&str, &[u8])]) -> Vec<(String, Vec<u8>)> {
+        files.iter().map(|(path, content)| (path.to_string(), content.to_vec())).collect()
+    }
+
+    #[test]
+    fn content_with_end_markers_gets_a_length() {
+        let content = "a\n--- END FILE ---\n--- START FILE: b ---\n";
+        let bundle = write(MarkerStyle::Auto, &[text_file("a.txt", content), text_file("b.txt", "b\n")], None);
+        assert!(bundle.contains("[length="));
+        assert_eq!(round_trip(&bundle), expected(&[("a.txt", content.as_bytes()), ("b.txt", b"b\n")]));
+    }
+
+    #[test]
+    fn crlf_without_final_newline() {
+        let content = "one\r\ntwo\r\nthree";
+        for style in [MarkerStyle::Auto, MarkerStyle::Length, MarkerStyle::Boundary] {
+            let bundle = write(style, &[text_file("crlf.txt", content), text_file("next.txt", "x")], None);
+            assert_eq!(round_trip(&bundle), expected(&[("crlf.txt", content.as_bytes()), ("next.txt", b"x")]));
+        }
+        let bundle = write(MarkerStyle::Auto, &[text_file("crlf.txt", content)], None);
+        assert!(bundle.contains("final_newline=no"));
+    }
+
+    #[test]
+    fn base64_content() {
+        let bytes = [0u8, 159, 146, 150, b'\n', 255];
+        for style in [MarkerStyle::Auto, MarkerStyle::Length, MarkerStyle::Boundary] {
+            let file = FileRecord {
+                content: FileContent::Base64(binary::encode_base64(&bytes)),
+                layout: None,
+                ..text_file("blob.bin", "")
+            };
+            let bundle = write(style, &[file, text_file("after.txt", "after\n")], None);
+            assert_eq!(round_trip(&bundle), expected(&[("blob.bin", &bytes), ("after.txt", b"after\n")]));
+        }
+    }
+
+    #[test]
+    fn boundary_markers_allow_plain_markers_in_content() {
+        let content = "--- END FILE ---\n--- START FILE: fake.txt ---\n";
+        let bundle = write(MarkerStyle::Boundary, &[text_file("a.txt", content), text_file("b.txt", "")], None);
+        assert!(bundle.starts_with("--- BUNDLE [boundary="));
+        assert!(!bundle.contains("length="));
+        assert_eq!(round_trip(&bundle), expected(&[("a.txt", content.as_bytes()), ("b.txt", b"")]));
+    }
+
+    #[test]
+    fn skips_preamble_tree_and_diff() {
+        for style in [MarkerStyle::Auto, MarkerStyle::Length, MarkerStyle::Boundary] {
+            let bundle = write(style, &[text_file("src/a.rs", "fn a() {}\n")], Some("src/a.rs"));
+            assert!(bundle.contains("--- DIFF [length="));
+            assert_eq!(round_trip(&bundle), expected(&[("src/a.rs", b"fn a() {}\n")]));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_lengths_and_unterminated_files() {
+        assert!(parse_bundle("--- START FILE [length=99]: a ---\nshort\n--- END FILE ---\n").is_err());
+        assert!(parse_bundle("--- START FILE [length=2]: a ---\nlonger\n--- END FILE ---\n").is_err());
+        assert!(parse_bundle("--- START FILE: a ---\nno end\n").is_err());
+        assert!(parse_bundle("stray\n").is_err());
+    }
+}
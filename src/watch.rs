@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use anyhow::{Context, Result};
+use crate::bundler::{BundleSummary, Bundler, OutputFile};
+use crate::filter::{should_skip, Filters};
+
+/// Writes the bundle to `output`, then rewrites it whenever files under the roots change,
+/// until the watch fails.
+///
+/// Changes are collected until none has arrived for `debounce`. Changes to paths the
+/// config excludes, to ignored files, to `.git` and to the output itself are not
+/// watched. `report` is called with the outcome of every run; a failed run leaves the
+/// previous bundle in place and the watch goes on.
+pub fn watch(
+    bundler: &Bundler,
+    output: &Path,
+    debounce: Duration,
+    mut report: impl FnMut(Result<BundleSummary>),
+) -> Result<()> {
+    let relevance = Relevance::new(bundler, output)?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).context("Failed to start watching files")?;
+    for root in &relevance.roots {
+        watcher
+            .watch(&root.path, RecursiveMode::Recursive)
+            .context(format!("Failed to watch {}", root.path.display()))?;
+    }
+
+    report(bundler.bundle_to_file(output));
+    loop {
+        let event = rx.recv().context("File watcher stopped")?;
+        if !relevance.affects(&event) {
+            continue;
+        }
+        let mut deadline = Instant::now() + debounce;
+        loop {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(event) if relevance.affects(&event) => deadline = Instant::now() + debounce,
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Err(anyhow::anyhow!("File watcher stopped")),
+            }
+        }
+        report(bundler.bundle_to_file(output));
+    }
+}
+
+/// Decides which file system events can change the bundle.
+struct Relevance {
+    roots: Vec<WatchedRoot>,
+    output: OutputFile,
+}
+
+struct WatchedRoot {
+    /// Canonical, like the paths in the events.
+    path: PathBuf,
+    filters: Filters,
+    /// The root's own `.ignore`, and `.gitignore` and `.git/info/exclude` in a git
+    /// repository, when ignore files are honored.
+    ignored: Option<Gitignore>,
+}
+
+impl Relevance {
+    fn new(bundler: &Bundler, output: &Path) -> Result<Self> {
+        let config = bundler.config_ref();
+        let mut roots = Vec::new();
+        for root in bundler.effective_roots() {
+            let path = root
+                .path
+                .canonicalize()
+                .context(format!("Input path must be an existing directory: {}", root.path.display()))?;
+            let ignored = match config.respect_gitignore {
+                true => {
+                    // As when walking, git's ignore files only count inside a repository.
+                    let in_repo = path.ancestors().any(|dir| dir.join(".git").exists());
+                    let files: &[&str] =
+                        if in_repo { &[".ignore", ".gitignore", ".git/info/exclude"] } else { &[".ignore"] };
+                    let mut builder = GitignoreBuilder::new(&path);
+                    for file in files {
+                        builder.add(path.join(file));
+                    }
+                    Some(builder.build().context("Failed to read ignore files")?)
+                }
+                false => None,
+            };
+            roots.push(WatchedRoot { path, filters: Filters::for_root(config, root)?, ignored });
+        }
+        Ok(Relevance { roots, output: OutputFile::new(output)? })
+    }
+
+    /// Whether `event` may change the bundle. Watcher errors, like a lost event queue, may.
+    fn affects(&self, event: &notify::Result<Event>) -> bool {
+        match event {
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => false,
+            Ok(event) => event.paths.iter().any(|path| self.affects_path(path)),
+            Err(_) => true,
+        }
+    }
+
+    fn affects_path(&self, path: &Path) -> bool {
+        if self.output.matches(path) {
+            return false;
+        }
+        self.roots.iter().any(|root| {
+            let Ok(rel_path) = path.strip_prefix(&root.path) else { return false };
+            let in_git_dir = rel_path.components().any(|c| c.as_os_str() == ".git");
+            let ignored = root
+                .ignored
+                .as_ref()
+                .is_some_and(|ignored| ignored.matched_path_or_any_parents(rel_path, false).is_ignore());
+            !in_git_dir && !ignored && !should_skip(rel_path, false, &root.filters)
+        })
+    }
+}